@@ -1,11 +1,62 @@
 /* tslint:disable */
 /* eslint-disable */
-export function create_renderer(canvas: HTMLCanvasElement): Promise<RendererHandle>;
 export function wasm_start(): void;
+export function create_renderer(canvas: HTMLCanvasElement): Promise<RendererHandle>;
 export class RendererHandle {
   private constructor();
   free(): void;
   [Symbol.dispose](): void;
+  /**
+   * Returns the caret before UTF-16 offset `index` into the text of a `Text` or `RichText`
+   * opcode as a zero-width `{ x, y, width, height }` spanning its line, relative to the
+   * text origin. Carets of vertical text are zero-height and span their column.
+   */
+  text_caret(op: Uint8Array, index: number): any;
+  /**
+   * Lays out a single encoded `Text` or `RichText` opcode exactly as `apply` would draw it
+   * and returns `{ width, height, lines: [{ text, width, x, baseline, ascent, descent,
+   * height }] }`, with positions relative to the text origin. Lines of vertical text are
+   * measured along their columns, with the baseline at the column's centre line.
+   */
+  measure_text(op: Uint8Array): any;
+  /**
+   * Returns the UTF-16 offset into the text of a `Text` or `RichText` opcode of the
+   * grapheme boundary nearest to `(x, y)`, relative to the text origin.
+   */
+  hit_test_text(op: Uint8Array, x: number, y: number): number;
+  /**
+   * Registers a TrueType/OpenType face for `Text` runs that ask for `family`.
+   * `style` is a CSS font style and `index` selects a face within a TTC collection.
+   */
+  register_font(bytes: Uint8Array, family: string, weight: number, style: string, index?: number | null): void;
+  /**
+   * Registers straight-alpha RGBA8 pixels under `id` for use by the `Image` opcode,
+   * replacing any image previously registered with that id.
+   */
+  register_image(id: number, width: number, height: number, pixels: Uint8Array): void;
+  /**
+   * Returns the `{ x, y, width, height }` rectangles covering the UTF-16 range
+   * `start..end` of the text of a `Text` or `RichText` opcode, relative to the text
+   * origin.
+   */
+  text_selection(op: Uint8Array, start: number, end: number): any;
+  /**
+   * Removes every face registered for `family`.
+   */
+  unregister_font(family: string): void;
+  unregister_image(id: number): void;
+  /**
+   * Sets the families tried, in order, for characters missing from a run's own fonts.
+   */
+  set_font_fallbacks(families: string[]): void;
+  /**
+   * Decodes PNG or JPEG bytes and registers the result under `id`.
+   */
+  register_encoded_image(id: number, bytes: Uint8Array): void;
+  /**
+   * Encodes a frame of opcodes into the scene drawn by `render`. Nodes with invalid
+   * data are skipped with a console warning rather than failing the frame.
+   */
   apply(ops: Uint8Array): void;
   render(): void;
   resize(width: number, height: number): void;
@@ -18,12 +69,22 @@ export interface InitOutput {
   readonly __wbg_rendererhandle_free: (a: number, b: number) => void;
   readonly create_renderer: (a: any) => any;
   readonly rendererhandle_apply: (a: number, b: any) => [number, number];
+  readonly rendererhandle_hit_test_text: (a: number, b: any, c: number, d: number) => [number, number, number];
+  readonly rendererhandle_measure_text: (a: number, b: any) => [number, number, number];
+  readonly rendererhandle_register_encoded_image: (a: number, b: number, c: any) => [number, number];
+  readonly rendererhandle_register_font: (a: number, b: any, c: number, d: number, e: number, f: number, g: number, h: number) => [number, number];
+  readonly rendererhandle_register_image: (a: number, b: number, c: number, d: number, e: any) => [number, number];
   readonly rendererhandle_render: (a: number) => [number, number];
   readonly rendererhandle_resize: (a: number, b: number, c: number) => void;
+  readonly rendererhandle_set_font_fallbacks: (a: number, b: number, c: number) => void;
+  readonly rendererhandle_text_caret: (a: number, b: any, c: number) => [number, number, number];
+  readonly rendererhandle_text_selection: (a: number, b: any, c: number, d: number) => [number, number, number];
+  readonly rendererhandle_unregister_font: (a: number, b: number, c: number) => void;
+  readonly rendererhandle_unregister_image: (a: number, b: number) => void;
   readonly wasm_start: () => void;
-  readonly wasm_bindgen__convert__closures_____invoke__h1e3f4f5e5b6e003f: (a: number, b: number, c: any) => void;
-  readonly wasm_bindgen__closure__destroy__hb8467f3e511a960c: (a: number, b: number) => void;
-  readonly wasm_bindgen__convert__closures_____invoke__h2a0f84921f614cc0: (a: number, b: number, c: any, d: any) => void;
+  readonly wasm_bindgen__convert__closures_____invoke__h614e34147b4393f3: (a: number, b: number, c: any) => void;
+  readonly wasm_bindgen__closure__destroy__hab47f4e828978c73: (a: number, b: number) => void;
+  readonly wasm_bindgen__convert__closures_____invoke__hb33e66535c9be72b: (a: number, b: number, c: any, d: any) => void;
   readonly __wbindgen_malloc: (a: number, b: number) => number;
   readonly __wbindgen_realloc: (a: number, b: number, c: number, d: number) => number;
   readonly __wbindgen_exn_store: (a: number) => void;
@@ -0,0 +1,434 @@
+use std::sync::Arc;
+
+use harfrust::{ShaperData, ShaperInstance};
+use skrifa::instance::NormalizedCoord;
+use skrifa::{FontRef, MetadataProvider, Tag};
+use vello::kurbo::Affine;
+use vello::peniko::{Blob, FontData};
+
+const DEFAULT_FONT_BYTES: &[u8] = include_bytes!("../assets/space-grotesk-regular.ttf");
+const DEFAULT_FONT_FAMILY: &str = "Space Grotesk";
+/// Stroke width, in ems, of synthetic bold; FreeType's emboldening strength.
+const SYNTHETIC_BOLD_WIDTH: f32 = 1.0 / 24.0;
+
+/// CSS `font-style` of a registered face or a text run.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum FontStyle {
+    Normal,
+    Italic,
+    Oblique,
+}
+
+impl FontStyle {
+    pub(crate) fn from_u8(value: u8) -> Self {
+        match value {
+            1 => FontStyle::Italic,
+            2 => FontStyle::Oblique,
+            _ => FontStyle::Normal,
+        }
+    }
+
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "normal" => Some(FontStyle::Normal),
+            "italic" => Some(FontStyle::Italic),
+            "oblique" => Some(FontStyle::Oblique),
+            _ => None,
+        }
+    }
+
+    /// Order in which faces of each style are tried, per the CSS font matching algorithm.
+    fn rank(self, candidate: FontStyle) -> u8 {
+        match (self, candidate) {
+            (a, b) if a == b => 0,
+            (FontStyle::Normal, FontStyle::Oblique) => 1,
+            (FontStyle::Italic, FontStyle::Oblique) => 1,
+            (FontStyle::Oblique, FontStyle::Italic) => 1,
+            _ => 2,
+        }
+    }
+}
+
+/// A registered face together with the shaping tables cached for it.
+#[derive(Clone)]
+pub(crate) struct Font {
+    pub(crate) data: FontData,
+    pub(crate) shaper_data: Arc<ShaperData>,
+}
+
+impl Font {
+    fn new(data: FontData) -> Result<Self, String> {
+        let font_ref = FontRef::from_index(data.data.as_ref(), data.index)
+            .map_err(|_| format!("Invalid font data at index {}", data.index))?;
+        let shaper_data = Arc::new(ShaperData::new(&font_ref));
+        Ok(Font { data, shaper_data })
+    }
+
+    fn is_same_face(&self, other: &Font) -> bool {
+        self.data.data.id() == other.data.data.id() && self.data.index == other.data.index
+    }
+}
+
+/// The font a text span asks for.
+pub(crate) struct FontQuery {
+    /// CSS family list such as `"Inter, sans-serif"`.
+    pub(crate) family: String,
+    pub(crate) weight: u16,
+    /// CSS `font-stretch` as a percentage of the normal width.
+    pub(crate) width: f32,
+    pub(crate) style: FontStyle,
+    /// Clockwise slant in degrees of oblique text, also used for italic text in faces
+    /// without an `ital` axis.
+    pub(crate) slant: f32,
+    /// Axis values that override those derived from weight, width and style.
+    pub(crate) variations: Vec<(Tag, f32)>,
+}
+
+/// A face of a font chain at the variation instance of a `FontQuery`, with the boldness
+/// or slant the face cannot provide synthesized.
+pub(crate) struct FontInstance {
+    pub(crate) font: Font,
+    pub(crate) shaper_instance: ShaperInstance,
+    /// Stroke width, in ems, drawn around glyphs of faces lighter than requested.
+    pub(crate) embolden: f32,
+    /// Tangent of the slant applied to glyphs of upright faces asked to be italic or oblique.
+    pub(crate) skew: f32,
+}
+
+impl FontInstance {
+    /// Normalized variation coordinates, in axis order.
+    pub(crate) fn coords(&self) -> &[NormalizedCoord] {
+        self.shaper_instance.coords()
+    }
+
+    /// Transform of each glyph outline, in the font's y-up space.
+    pub(crate) fn glyph_transform(&self) -> Option<Affine> {
+        (self.skew != 0.0).then(|| Affine::skew(self.skew as f64, 0.0))
+    }
+}
+
+struct FontFace {
+    family: String,
+    weight: u16,
+    style: FontStyle,
+    font: Font,
+}
+
+/// Fonts available to the `Text` opcode, matched by family, weight and style.
+pub(crate) struct FontRegistry {
+    faces: Vec<FontFace>,
+    fallbacks: Vec<String>,
+    default: Font,
+}
+
+impl FontRegistry {
+    pub(crate) fn new() -> Self {
+        let data = FontData::new(Blob::new(Arc::new(DEFAULT_FONT_BYTES)), 0);
+        let default = Font::new(data).expect("bundled font is valid");
+        let mut registry = FontRegistry {
+            faces: Vec::new(),
+            fallbacks: Vec::new(),
+            default: default.clone(),
+        };
+        registry.insert(default, DEFAULT_FONT_FAMILY, 400, FontStyle::Normal);
+        registry
+    }
+
+    /// Adds a face, replacing any face already registered with the same family, weight and style.
+    pub(crate) fn register(
+        &mut self,
+        bytes: Vec<u8>,
+        index: u32,
+        family: &str,
+        weight: u16,
+        style: FontStyle,
+    ) -> Result<(), String> {
+        let font = Font::new(FontData::new(Blob::new(Arc::new(bytes)), index))?;
+        self.insert(font, family, weight, style);
+        Ok(())
+    }
+
+    /// Removes every face of `family`.
+    pub(crate) fn unregister(&mut self, family: &str) {
+        let family = normalize_family(family);
+        self.faces.retain(|face| face.family != family);
+    }
+
+    /// Sets the families consulted, in order, for codepoints the requested family lacks.
+    pub(crate) fn set_fallbacks(&mut self, families: Vec<String>) {
+        self.fallbacks = families.iter().map(|family| normalize_family(family)).collect();
+    }
+
+    fn insert(&mut self, font: Font, family: &str, weight: u16, style: FontStyle) {
+        let family = normalize_family(family);
+        let weight = weight.clamp(1, 1000);
+        self.faces
+            .retain(|face| !(face.family == family && face.weight == weight && face.style == style));
+        self.faces.push(FontFace {
+            family,
+            weight,
+            style,
+            font,
+        });
+    }
+
+    /// Resolves the family list of `query` to the best matching face, followed by every
+    /// other face to try for missing glyphs: the remaining families of the list, the registry
+    /// fallbacks, then the bundled default font.
+    pub(crate) fn resolve_chain(&self, query: &FontQuery) -> Vec<FontInstance> {
+        let mut chain: Vec<FontInstance> = Vec::new();
+        let default_family = normalize_family(DEFAULT_FONT_FAMILY);
+        let candidates = query
+            .family
+            .split(',')
+            .map(normalize_family)
+            .chain(self.fallbacks.iter().cloned())
+            .chain(std::iter::once(default_family));
+        for family in candidates {
+            let Some(face) = self.best_match(&family, query.weight, query.style) else {
+                continue;
+            };
+            if !chain
+                .iter()
+                .any(|existing| existing.font.is_same_face(&face.font))
+            {
+                chain.push(instantiate(&face.font, face.weight, face.style, query));
+            }
+        }
+        if chain.is_empty() {
+            chain.push(instantiate(&self.default, 400, FontStyle::Normal, query));
+        }
+        chain
+    }
+
+    fn best_match(&self, family: &str, weight: u16, style: FontStyle) -> Option<&FontFace> {
+        self.faces
+            .iter()
+            .filter(|face| face.family == family)
+            .min_by_key(|face| (style.rank(face.style), weight_rank(weight, face.weight)))
+    }
+}
+
+/// Maps `query` onto the `wght`, `wdth`, `ital` and `slnt` axes of a face registered with
+/// `weight` and `style`. Like browsers, bold is synthesized for weights of 600 and above
+/// and a slant for italic and oblique text when neither the face nor its axes provide them.
+fn instantiate(font: &Font, weight: u16, style: FontStyle, query: &FontQuery) -> FontInstance {
+    let mut instance = FontInstance {
+        font: font.clone(),
+        shaper_instance: ShaperInstance::default(),
+        embolden: 0.0,
+        skew: 0.0,
+    };
+    let Ok(font_ref) = FontRef::from_index(font.data.data.as_ref(), font.data.index) else {
+        return instance;
+    };
+    let axes = font_ref.axes();
+    let has_axis = |tag: &[u8; 4]| axes.iter().any(|axis| axis.tag() == Tag::new(tag));
+
+    let mut variations = vec![
+        (Tag::new(b"wght"), query.weight as f32),
+        (Tag::new(b"wdth"), query.width),
+    ];
+    let ital = (Tag::new(b"ital"), 1.0);
+    // `slnt` is counter-clockwise, CSS oblique angles clockwise.
+    let slnt = (Tag::new(b"slnt"), -query.slant);
+    match query.style {
+        FontStyle::Normal => {}
+        FontStyle::Italic if has_axis(b"ital") => variations.push(ital),
+        FontStyle::Oblique if !has_axis(b"slnt") => variations.push(ital),
+        FontStyle::Italic | FontStyle::Oblique => variations.push(slnt),
+    }
+    variations.extend_from_slice(&query.variations);
+    instance.shaper_instance = ShaperInstance::from_variations(&font_ref, &variations);
+
+    if query.weight >= 600 && weight < 600 && !has_axis(b"wght") {
+        instance.embolden = SYNTHETIC_BOLD_WIDTH;
+    }
+    if query.style != FontStyle::Normal
+        && style == FontStyle::Normal
+        && !has_axis(b"ital")
+        && !has_axis(b"slnt")
+    {
+        instance.skew = query.slant.to_radians().tan();
+    }
+    instance
+}
+
+fn normalize_family(family: &str) -> String {
+    family
+        .trim()
+        .trim_matches(|c| c == '"' || c == '\'')
+        .trim()
+        .to_lowercase()
+}
+
+/// Orders candidate weights per the CSS font matching algorithm: weights between the
+/// desired weight and 500 first, then lighter, then heavier for 400 and 500; lighter
+/// first below 400; heavier first above 500.
+fn weight_rank(desired: u16, candidate: u16) -> (u8, u16) {
+    let distance = desired.abs_diff(candidate);
+    if candidate == desired {
+        (0, 0)
+    } else if (400..=500).contains(&desired) {
+        if candidate > desired && candidate <= 500 {
+            (1, distance)
+        } else if candidate < desired {
+            (2, distance)
+        } else {
+            (3, distance)
+        }
+    } else if (desired < 400) == (candidate < desired) {
+        (1, distance)
+    } else {
+        (2, distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A registry with the bundled face registered as family "Test" at each weight and style.
+    fn with_faces(faces: &[(u16, FontStyle)]) -> FontRegistry {
+        let mut registry = FontRegistry::new();
+        for &(weight, style) in faces {
+            registry
+                .register(DEFAULT_FONT_BYTES.to_vec(), 0, "Test", weight, style)
+                .unwrap();
+        }
+        registry
+    }
+
+    fn matched(registry: &FontRegistry, weight: u16, style: FontStyle) -> (u16, FontStyle) {
+        let face = registry.best_match("test", weight, style).unwrap();
+        (face.weight, face.style)
+    }
+
+    fn query(family: &str) -> FontQuery {
+        FontQuery {
+            family: family.to_string(),
+            weight: 400,
+            width: 100.0,
+            style: FontStyle::Normal,
+            slant: 14.0,
+            variations: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn weights_from_400_to_500_try_up_to_500_then_lighter() {
+        let registry = with_faces(&[
+            (300, FontStyle::Normal),
+            (450, FontStyle::Normal),
+            (600, FontStyle::Normal),
+        ]);
+        assert_eq!(matched(&registry, 400, FontStyle::Normal).0, 450);
+        // Nothing lies between 500 and 500, so lighter faces come before heavier ones.
+        assert_eq!(matched(&registry, 500, FontStyle::Normal).0, 450);
+
+        let registry = with_faces(&[(300, FontStyle::Normal), (600, FontStyle::Normal)]);
+        assert_eq!(matched(&registry, 400, FontStyle::Normal).0, 300);
+    }
+
+    #[test]
+    fn light_weights_try_lighter_first_and_bold_heavier_first() {
+        let registry = with_faces(&[
+            (200, FontStyle::Normal),
+            (350, FontStyle::Normal),
+            (700, FontStyle::Normal),
+        ]);
+        assert_eq!(matched(&registry, 300, FontStyle::Normal).0, 200);
+        assert_eq!(matched(&registry, 100, FontStyle::Normal).0, 200);
+        assert_eq!(matched(&registry, 600, FontStyle::Normal).0, 700);
+        assert_eq!(matched(&registry, 800, FontStyle::Normal).0, 700);
+    }
+
+    #[test]
+    fn italic_falls_back_to_oblique_before_normal() {
+        let registry = with_faces(&[(400, FontStyle::Normal), (400, FontStyle::Oblique)]);
+        assert_eq!(matched(&registry, 400, FontStyle::Italic).1, FontStyle::Oblique);
+
+        // Style is matched before weight.
+        let registry = with_faces(&[(400, FontStyle::Normal), (700, FontStyle::Italic)]);
+        assert_eq!(matched(&registry, 400, FontStyle::Italic), (700, FontStyle::Italic));
+        assert_eq!(matched(&registry, 400, FontStyle::Oblique), (700, FontStyle::Italic));
+    }
+
+    #[test]
+    fn unregister_removes_every_face_of_a_family() {
+        let mut registry = with_faces(&[(400, FontStyle::Normal), (700, FontStyle::Italic)]);
+        registry.unregister(" 'TEST' ");
+        assert!(registry.best_match("test", 400, FontStyle::Normal).is_none());
+        assert!(registry.best_match("space grotesk", 400, FontStyle::Normal).is_some());
+    }
+
+    #[test]
+    fn chains_list_each_face_once() {
+        let mut registry = with_faces(&[(400, FontStyle::Normal)]);
+        registry.set_fallbacks(vec!["Test".to_string(), "Space Grotesk".to_string()]);
+        let chain = registry.resolve_chain(&query("Test, 'test', Space Grotesk, Missing"));
+        assert_eq!(chain.len(), 2);
+        assert!(chain[0].font.is_same_face(&registry.faces[1].font));
+        assert!(chain[1].font.is_same_face(&registry.default));
+
+        // Unknown families are skipped, leaving the fallbacks.
+        let chain = registry.resolve_chain(&query("Missing"));
+        assert_eq!(chain.len(), 2);
+        assert!(chain[1].font.is_same_face(&registry.default));
+    }
+
+    /// The bundled face with its `fvar` table renamed, so it has no variation axes.
+    fn static_face() -> Vec<u8> {
+        let mut bytes = DEFAULT_FONT_BYTES.to_vec();
+        let tables = u16::from_be_bytes([bytes[4], bytes[5]]) as usize;
+        let record = (0..tables)
+            .map(|index| 12 + 16 * index)
+            .find(|&record| &bytes[record..record + 4] == b"fvar")
+            .unwrap();
+        // Still sorts between `cmap` and `gasp` in the table directory.
+        bytes[record..record + 4].copy_from_slice(b"fvaq");
+        bytes
+    }
+
+    #[test]
+    fn faces_without_a_weight_axis_are_emboldened() {
+        let mut registry = FontRegistry::new();
+        registry
+            .register(static_face(), 0, "Static", 400, FontStyle::Normal)
+            .unwrap();
+        let bold = FontQuery { weight: 700, ..query("Static") };
+        let chain = registry.resolve_chain(&bold);
+        assert!(chain[0].coords().is_empty());
+        assert_eq!(chain[0].embolden, SYNTHETIC_BOLD_WIDTH);
+        assert_eq!(registry.resolve_chain(&query("Static"))[0].embolden, 0.0);
+
+        // The bundled face reaches 700 on its `wght` axis instead.
+        let chain = registry.resolve_chain(&FontQuery { weight: 700, ..query("") });
+        assert_eq!(chain[0].embolden, 0.0);
+        assert!(!chain[0].coords().is_empty());
+    }
+
+    #[test]
+    fn upright_faces_are_slanted_for_italic() {
+        let registry = FontRegistry::new();
+        let chain = registry.resolve_chain(&FontQuery { style: FontStyle::Italic, ..query("") });
+        assert_eq!(chain[0].skew, 14f32.to_radians().tan());
+        assert!(chain[0].glyph_transform().is_some());
+        assert_eq!(registry.resolve_chain(&query(""))[0].skew, 0.0);
+    }
+
+    #[test]
+    fn variations_override_derived_axes() {
+        let registry = FontRegistry::new();
+        let coords = |query: &FontQuery| registry.resolve_chain(query)[0].coords().to_vec();
+        let light = coords(&FontQuery { weight: 300, ..query("") });
+        let bold = coords(&FontQuery { weight: 700, ..query("") });
+        assert_ne!(light, bold);
+        let overridden = FontQuery {
+            weight: 700,
+            variations: vec![(Tag::new(b"wght"), 300.0)],
+            ..query("")
+        };
+        assert_eq!(coords(&overridden), light);
+    }
+}
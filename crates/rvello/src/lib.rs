@@ -1,17 +1,35 @@
+use std::collections::HashMap;
+use std::f64::consts::{FRAC_PI_2, PI};
 use std::mem;
+use std::ops::Range;
 use std::sync::Arc;
 
-use js_sys::Uint8Array;
-use skrifa::charmap::Charmap;
-use skrifa::instance::{LocationRef, Size};
-use skrifa::metrics::GlyphMetrics;
-use skrifa::{FontRef, GlyphId, MetadataProvider};
+use js_sys::{Array, Object, Reflect, Uint8Array};
+use skrifa::Tag;
 use wasm_bindgen::prelude::*;
 use web_sys::HtmlCanvasElement;
 
-use vello::kurbo::{Affine, BezPath, RoundedRect, Stroke};
-use vello::peniko::{Blob, Color, Fill, FontData};
+use vello::kurbo::{
+    Affine, BezPath, Cap, Join, Line, Point, Rect, RoundedRect, RoundedRectRadii, Shape, Stroke, Vec2,
+};
+use vello::peniko::{
+    BlendMode, Blob, Brush, Color, ColorStop, Compose, Extend, Fill, Gradient, ImageAlphaType, ImageBrush,
+    ImageData, ImageFormat, ImageSampler, Mix, StyleRef,
+};
 use vello::{wgpu, AaConfig, Renderer, RendererOptions, Scene};
+use vello_encoding::{DrawBeginClip, NormalizedCoord, Transform};
+
+mod fonts;
+mod text;
+mod text_path;
+
+use fonts::{FontInstance, FontQuery, FontRegistry, FontStyle};
+use text::{
+    layout_text, DecorationKind, DecorationLine, DecorationStyle, FontChain, GlyphRun, OverflowWrap,
+    TextAlign, TextDecoration, TextDirection, TextLayout, TextLayoutOptions, TextOrientation, TextOverflow,
+    TextSpan, WhiteSpace, WritingMode,
+};
+use text_path::{glyph_runs_on_path, PathSide, TextPath};
 
 #[wasm_bindgen]
 pub struct RendererHandle {
@@ -22,9 +40,7 @@ pub struct RendererHandle {
     surface: wgpu::Surface<'static>,
     config: wgpu::SurfaceConfiguration,
     renderer: Renderer,
-    scene: Scene,
-    font: FontData,
-    base_color: Color,
+    encoder: SceneEncoder,
     storage_format: wgpu::TextureFormat,
     offscreen: Option<OffscreenTarget>,
     sampler: wgpu::Sampler,
@@ -46,11 +62,16 @@ struct PresentPipeline {
     format: wgpu::TextureFormat,
 }
 
-const DEFAULT_FONT_BYTES: &[u8] = include_bytes!("../assets/space-grotesk-regular.ttf");
-
-fn default_font_data() -> FontData {
-    let blob = Blob::new(Arc::new(DEFAULT_FONT_BYTES));
-    FontData::new(blob, 0)
+/// Builds the scene of each frame from the opcode stream, with the fonts and images
+/// registered for it. It holds no GPU resources, so frames encode without a device.
+struct SceneEncoder {
+    scene: Scene,
+    fonts: FontRegistry,
+    images: HashMap<u32, ImageData>,
+    base_color: Color,
+    /// Target size in device pixels, covered by layers without a clip shape.
+    width: u32,
+    height: u32,
 }
 
 #[wasm_bindgen]
@@ -63,7 +84,7 @@ pub async fn create_renderer(canvas: HtmlCanvasElement) -> Result<RendererHandle
     });
 
     let surface = instance
-        .create_surface(wgpu::SurfaceTarget::Canvas(canvas.clone()))
+        .create_surface(canvas_target(&canvas)?)
         .map_err(|err| js_error(&format!("Failed to create WebGPU surface: {err:?}")))?;
 
     let adapter = instance
@@ -154,9 +175,7 @@ pub async fn create_renderer(canvas: HtmlCanvasElement) -> Result<RendererHandle
         surface: leak_surface(surface),
         config,
         renderer,
-        scene: Scene::new(),
-        font: default_font_data(),
-        base_color: Color::new([0.0, 0.0, 0.0, 1.0]),
+        encoder: SceneEncoder::new(width, height),
         storage_format,
         offscreen: None,
         sampler,
@@ -173,6 +192,8 @@ impl RendererHandle {
         if width == 0 || height == 0 {
             return;
         }
+        self.encoder.width = width;
+        self.encoder.height = height;
         if self.config.width == width && self.config.height == height {
             return;
         }
@@ -183,233 +204,156 @@ impl RendererHandle {
         self.present_bind_group = None;
     }
 
+    /// Registers straight-alpha RGBA8 pixels under `id` for use by the `Image` opcode,
+    /// replacing any image previously registered with that id.
     #[wasm_bindgen]
-    pub fn apply(&mut self, ops: Uint8Array) -> Result<(), JsValue> {
-        let bytes = ops.to_vec();
-        let mut decoder = Decoder::new(&bytes);
-
-        self.scene.reset();
-        self.base_color = Color::new([0.0, 0.0, 0.0, 1.0]);
-
-        while let Some(op) = decoder.next_opcode()? {
-            match op {
-                OpCode::BeginFrame => {
-                    let logical_width = decoder.read_f32()?;
-                    let logical_height = decoder.read_f32()?;
-                    let dpr = decoder.read_f32()?;
-                    let width = (logical_width * dpr).round().clamp(1.0, f32::MAX) as u32;
-                    let height = (logical_height * dpr).round().clamp(1.0, f32::MAX) as u32;
-                    self.resize(width, height);
-                    let r = decoder.read_f32()?;
-                    let g = decoder.read_f32()?;
-                    let b = decoder.read_f32()?;
-                    let a = decoder.read_f32()?;
-                    self.base_color = Color::new([r, g, b, a]);
-                }
-                OpCode::Rect => {
-                    let opacity = decoder.read_f32()?;
-                    let transform = decoder.read_mat3()?;
-                    let ox = decoder.read_f32()?;
-                    let oy = decoder.read_f32()?;
-                    let width = decoder.read_f32()?;
-                    let height = decoder.read_f32()?;
-                    let radius = decoder.read_f32()?;
-                    let r = decoder.read_f32()?;
-                    let g = decoder.read_f32()?;
-                    let b = decoder.read_f32()?;
-                    let a = decoder.read_f32()?;
-
-                    let color = Color::new([r, g, b, (a * opacity).clamp(0.0, 1.0)]);
-                    let rect = RoundedRect::new(
-                        ox as f64,
-                        oy as f64,
-                        (ox + width) as f64,
-                        (oy + height) as f64,
-                        radius as f64,
-                    );
-                    let affine = Affine::new([
-                        transform[0] as f64,
-                        transform[1] as f64,
-                        transform[2] as f64,
-                        transform[3] as f64,
-                        transform[4] as f64,
-                        transform[5] as f64,
-                    ]);
-
-                    self.scene.fill(Fill::NonZero, affine, color, None, &rect);
-                }
-                OpCode::Path => {
-                    let opacity = decoder.read_f32()?;
-                    let transform = decoder.read_mat3()?;
-                    let fill_rule = decoder.read_u8()?;
+    pub fn register_image(
+        &mut self,
+        id: u32,
+        width: u32,
+        height: u32,
+        pixels: Uint8Array,
+    ) -> Result<(), JsValue> {
+        let expected = ImageFormat::Rgba8
+            .size_in_bytes(width, height)
+            .ok_or_else(|| js_error("Image dimensions overflow"))?;
+        if width == 0 || height == 0 || pixels.length() as usize != expected {
+            return Err(js_error("Image pixel data does not match its dimensions"));
+        }
+        self.encoder.insert_image(id, width, height, pixels.to_vec());
+        Ok(())
+    }
 
-                    let affine = Affine::new([
-                        transform[0] as f64,
-                        transform[1] as f64,
-                        transform[2] as f64,
-                        transform[3] as f64,
-                        transform[4] as f64,
-                        transform[5] as f64,
-                    ]);
-
-                    // Read fill
-                    let has_fill = decoder.read_u8()? != 0;
-                    let fill_color = if has_fill {
-                        let r = decoder.read_f32()?;
-                        let g = decoder.read_f32()?;
-                        let b = decoder.read_f32()?;
-                        let a = decoder.read_f32()?;
-                        Some(Color::new([r, g, b, (a * opacity).clamp(0.0, 1.0)]))
-                    } else {
-                        None
-                    };
+    /// Decodes PNG or JPEG bytes and registers the result under `id`.
+    #[wasm_bindgen]
+    pub fn register_encoded_image(&mut self, id: u32, bytes: Uint8Array) -> Result<(), JsValue> {
+        let decoded = image::load_from_memory(&bytes.to_vec())
+            .map_err(|err| js_error(&format!("Failed to decode image: {err}")))?
+            .into_rgba8();
+        let (width, height) = decoded.dimensions();
+        self.encoder.insert_image(id, width, height, decoded.into_raw());
+        Ok(())
+    }
 
-                    // Read stroke
-                    let has_stroke = decoder.read_u8()? != 0;
-                    let stroke_info = if has_stroke {
-                        let width = decoder.read_f32()?;
-                        let r = decoder.read_f32()?;
-                        let g = decoder.read_f32()?;
-                        let b = decoder.read_f32()?;
-                        let a = decoder.read_f32()?;
-                        Some((width, Color::new([r, g, b, (a * opacity).clamp(0.0, 1.0)])))
-                    } else {
-                        None
-                    };
+    #[wasm_bindgen]
+    pub fn unregister_image(&mut self, id: u32) {
+        self.encoder.images.remove(&id);
+    }
 
-                    // Read path data
-                    let path_len = decoder.read_u32()?;
-                    let path_str = decoder.read_string(path_len as usize)?;
+    /// Encodes a frame of opcodes into the scene drawn by `render`. Nodes with invalid
+    /// data are skipped with a console warning rather than failing the frame.
+    #[wasm_bindgen]
+    pub fn apply(&mut self, ops: Uint8Array) -> Result<(), JsValue> {
+        let warnings = self.encoder.encode(&ops.to_vec())?;
+        self.resize(self.encoder.width, self.encoder.height);
+        for warning in warnings {
+            web_sys::console::warn_1(&JsValue::from_str(&format!("[rvello] {warning}")));
+        }
+        Ok(())
+    }
 
-                    // Parse SVG path string
-                    if let Ok(bez_path) = BezPath::from_svg(&path_str) {
-                        let fill_style = if fill_rule == 1 {
-                            Fill::EvenOdd
-                        } else {
-                            Fill::NonZero
-                        };
+    /// Registers a TrueType/OpenType face for `Text` runs that ask for `family`.
+    /// `style` is a CSS font style and `index` selects a face within a TTC collection.
+    #[wasm_bindgen]
+    pub fn register_font(
+        &mut self,
+        bytes: Uint8Array,
+        family: &str,
+        weight: u16,
+        style: &str,
+        index: Option<u32>,
+    ) -> Result<(), JsValue> {
+        let style = FontStyle::parse(style).ok_or_else(|| js_error("Unknown font style"))?;
+        self.encoder
+            .fonts
+            .register(bytes.to_vec(), index.unwrap_or(0), family, weight, style)
+            .map_err(|err| js_error(&err))
+    }
 
-                        if let Some(color) = fill_color {
-                            self.scene.fill(fill_style, affine, color, None, &bez_path);
-                        }
+    /// Removes every face registered for `family`.
+    #[wasm_bindgen]
+    pub fn unregister_font(&mut self, family: &str) {
+        self.encoder.fonts.unregister(family);
+    }
 
-                        if let Some((width, color)) = stroke_info {
-                            let stroke = Stroke::new(width as f64);
-                            self.scene.stroke(&stroke, affine, color, None, &bez_path);
-                        }
-                    }
-                }
-                OpCode::Text => {
-                    let opacity = decoder.read_f32()?;
-                    let transform = decoder.read_mat3()?;
-                    let ox = decoder.read_f32()?;
-                    let oy = decoder.read_f32()?;
-                    let font_size = decoder.read_f32()?;
-                    let line_height = decoder.read_f32()?;
-                    let max_width = decoder.read_f32()?;
-                    let align = TextAlign::from_u8(decoder.read_u8()?);
-                    let r = decoder.read_f32()?;
-                    let g = decoder.read_f32()?;
-                    let b = decoder.read_f32()?;
-                    let a = decoder.read_f32()?;
-                    let text_len = decoder.read_u32()?;
-                    let text = decoder.read_string(text_len as usize)?;
+    /// Sets the families tried, in order, for characters missing from a run's own fonts.
+    #[wasm_bindgen]
+    pub fn set_font_fallbacks(&mut self, families: Vec<String>) {
+        self.encoder.fonts.set_fallbacks(families);
+    }
 
-                    if text.is_empty() {
-                        continue;
-                    }
+    /// Lays out a single encoded `Text` or `RichText` opcode exactly as `apply` would draw it
+    /// and returns `{ width, height, lines: [{ text, width, x, baseline, ascent, descent,
+    /// height }] }`, with positions relative to the text origin. Lines of vertical text are
+    /// measured along their columns, with the baseline at the column's centre line.
+    #[wasm_bindgen]
+    pub fn measure_text(&self, op: Uint8Array) -> Result<JsValue, JsValue> {
+        let op = decode_text_op(op)?;
+        let (_, layout) = self.encoder.layout_text(&op)?;
+
+        let lines = Array::new();
+        let mut extent = 0.0f32;
+        for placed in &layout.lines {
+            extent = extent.max(placed.line.width);
+            let line = Object::new();
+            set_property(&line, "text", &JsValue::from_str(&placed.line.text))?;
+            set_property(&line, "width", &placed.line.width.into())?;
+            set_property(&line, "x", &placed.x.into())?;
+            set_property(&line, "baseline", &placed.baseline.into())?;
+            set_property(&line, "ascent", &placed.line.ascent.into())?;
+            set_property(&line, "descent", &(-placed.line.descent).into())?;
+            set_property(&line, "height", &placed.line.height.into())?;
+            lines.push(&line);
+        }
 
-                    let font_size = if font_size.is_finite() && font_size > 0.0 {
-                        font_size
-                    } else {
-                        16.0
-                    };
-                    let font_ref = FontRef::from_index(self.font.data.as_ref(), self.font.index)
-                        .map_err(|_| js_error("Invalid font data"))?;
-                    let size = Size::new(font_size);
-                    let metrics = font_ref.metrics(size, LocationRef::default());
-                    let glyph_metrics = font_ref.glyph_metrics(size, LocationRef::default());
-                    let ascent = if metrics.ascent.is_finite() {
-                        metrics.ascent
-                    } else {
-                        font_size * 0.8
-                    };
-                    let descent = if metrics.descent.is_finite() {
-                        metrics.descent
-                    } else {
-                        -font_size * 0.2
-                    };
-                    let leading = if metrics.leading.is_finite() {
-                        metrics.leading
-                    } else {
-                        0.0
-                    };
-                    let fallback_width = metrics
-                        .average_width
-                        .filter(|width| width.is_finite() && *width > 0.0)
-                        .unwrap_or(font_size * 0.5);
-                    let line_height = if line_height.is_finite() && line_height > 0.0 {
-                        line_height
-                    } else {
-                        let base = ascent - descent + leading;
-                        if base.is_finite() && base > 0.0 {
-                            base
-                        } else {
-                            font_size * 1.2
-                        }
-                    };
+        let (width, height) = if layout.vertical {
+            (layout.height(), extent)
+        } else {
+            (extent, layout.height())
+        };
+        let metrics = Object::new();
+        set_property(&metrics, "width", &width.into())?;
+        set_property(&metrics, "height", &height.into())?;
+        set_property(&metrics, "lines", &lines)?;
+        Ok(metrics.into())
+    }
 
-                    let charmap = font_ref.charmap();
-                    let lines = wrap_text_lines(&text, max_width, &charmap, &glyph_metrics, fallback_width);
-                    if lines.is_empty() {
-                        continue;
-                    }
+    /// Returns the UTF-16 offset into the text of a `Text` or `RichText` opcode of the
+    /// grapheme boundary nearest to `(x, y)`, relative to the text origin.
+    #[wasm_bindgen]
+    pub fn hit_test_text(&self, op: Uint8Array, x: f32, y: f32) -> Result<u32, JsValue> {
+        let op = decode_text_op(op)?;
+        let (_, layout) = self.encoder.layout_text(&op)?;
+        let point = layout.frame().inverse() * Point::new(x as f64, y as f64);
+        let offset = layout.hit_test(&op.text, point.x as f32, point.y as f32);
+        Ok(utf16_index(&op.text, offset))
+    }
 
-                    let color = Color::new([r, g, b, (a * opacity).clamp(0.0, 1.0)]);
-                    let affine = Affine::new([
-                        transform[0] as f64,
-                        transform[1] as f64,
-                        transform[2] as f64,
-                        transform[3] as f64,
-                        transform[4] as f64,
-                        transform[5] as f64,
-                    ]);
-
-                    let mut glyphs = Vec::new();
-                    let mut y = oy + ascent;
-                    for line in lines {
-                        let offset_x = align_offset(align, max_width, line.width);
-                        let mut x = ox + offset_x;
-                        for ch in line.text.chars() {
-                            if ch == '\t' {
-                                x += fallback_width * 4.0;
-                                continue;
-                            }
-                            let glyph_id = charmap.map(ch).unwrap_or(GlyphId::NOTDEF);
-                            glyphs.push(vello::Glyph {
-                                id: glyph_id.to_u32(),
-                                x,
-                                y,
-                            });
-                            x += glyph_metrics.advance_width(glyph_id).unwrap_or(fallback_width);
-                        }
-                        y += line_height;
-                    }
+    /// Returns the caret before UTF-16 offset `index` into the text of a `Text` or `RichText`
+    /// opcode as a zero-width `{ x, y, width, height }` spanning its line, relative to the
+    /// text origin. Carets of vertical text are zero-height and span their column.
+    #[wasm_bindgen]
+    pub fn text_caret(&self, op: Uint8Array, index: u32) -> Result<JsValue, JsValue> {
+        let op = decode_text_op(op)?;
+        let (_, layout) = self.encoder.layout_text(&op)?;
+        let caret = layout.caret(&op.text, byte_index(&op.text, index));
+        rect_object(layout.frame().transform_rect_bbox(caret))
+    }
 
-                    if !glyphs.is_empty() {
-                        self.scene
-                            .draw_glyphs(&self.font)
-                            .font_size(font_size)
-                            .transform(affine)
-                            .brush(color)
-                            .draw(Fill::NonZero, glyphs.into_iter());
-                    }
-                }
-                OpCode::EndFrame => break,
-            }
+    /// Returns the `{ x, y, width, height }` rectangles covering the UTF-16 range
+    /// `start..end` of the text of a `Text` or `RichText` opcode, relative to the text
+    /// origin.
+    #[wasm_bindgen]
+    pub fn text_selection(&self, op: Uint8Array, start: u32, end: u32) -> Result<JsValue, JsValue> {
+        let op = decode_text_op(op)?;
+        let (_, layout) = self.encoder.layout_text(&op)?;
+        let (start, end) = (start.min(end), start.max(end));
+        let range = byte_index(&op.text, start)..byte_index(&op.text, end);
+        let rects = Array::new();
+        for rect in layout.selection(&op.text, range) {
+            rects.push(&rect_object(layout.frame().transform_rect_bbox(rect))?);
         }
-
-        Ok(())
+        Ok(rects.into())
     }
 
     #[wasm_bindgen]
@@ -445,7 +389,7 @@ impl RendererHandle {
             .create_view(&wgpu::TextureViewDescriptor::default());
         let width = self.config.width;
         let height = self.config.height;
-        let base_color = self.base_color;
+        let base_color = self.encoder.base_color;
         let offscreen_view = {
             let target = self.ensure_offscreen_target();
             target.view.clone()
@@ -460,7 +404,13 @@ impl RendererHandle {
         };
 
         self.renderer
-            .render_to_texture(&self.device, &self.queue, &self.scene, &offscreen_view, &params)
+            .render_to_texture(
+                &self.device,
+                &self.queue,
+                &self.encoder.scene,
+                &offscreen_view,
+                &params,
+            )
             .map_err(|err| JsValue::from_str(&format!("Render failed: {err:?}")))?;
 
         let mut encoder = self
@@ -496,131 +446,696 @@ impl RendererHandle {
     }
 }
 
-impl RendererHandle {
-    fn ensure_offscreen_target(&mut self) -> &OffscreenTarget {
-        let needs_recreate = self
-            .offscreen
-            .as_ref()
-            .map_or(true, |target| target.width != self.config.width || target.height != self.config.height);
-
-        if needs_recreate {
-            let format = self.storage_format;
-            let texture = self.device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("rvello-offscreen-texture"),
-                size: wgpu::Extent3d {
-                    width: self.config.width.max(1),
-                    height: self.config.height.max(1),
-                    depth_or_array_layers: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING
-                    | wgpu::TextureUsages::STORAGE_BINDING
-                    | wgpu::TextureUsages::COPY_SRC
-                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
-                view_formats: &[],
-            });
-            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-            self.offscreen = Some(OffscreenTarget {
-                texture,
-                view,
-                width: self.config.width,
-                height: self.config.height,
-            });
-            self.present_bind_group = None;
+impl SceneEncoder {
+    fn new(width: u32, height: u32) -> Self {
+        SceneEncoder {
+            scene: Scene::new(),
+            fonts: FontRegistry::new(),
+            images: HashMap::new(),
+            base_color: Color::new([0.0, 0.0, 0.0, 1.0]),
+            width,
+            height,
         }
-
-        self.offscreen.as_ref().unwrap()
     }
 
-    fn ensure_present_bind_group(&mut self, view: &wgpu::TextureView) -> &wgpu::BindGroup {
-        if self.present_bind_group.is_none() {
-            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("rvello-present-bind-group"),
-                layout: &self.present_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::Sampler(&self.sampler),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::TextureView(view),
-                    },
-                ],
-            });
-            self.present_bind_group = Some(bind_group);
+    /// Replaces the scene with the frame encoded in `bytes`, returning a warning for each
+    /// node skipped because of invalid data. A frame that fails to decode leaves the scene
+    /// empty rather than partly encoded.
+    fn encode(&mut self, bytes: &[u8]) -> Result<Vec<String>, String> {
+        self.scene.reset();
+        self.base_color = Color::new([0.0, 0.0, 0.0, 1.0]);
+
+        let result = self.encode_ops(bytes);
+        if result.is_err() {
+            self.scene.reset();
         }
-        self.present_bind_group.as_ref().unwrap()
+        result
     }
 
-    fn ensure_present_pipeline(&mut self) -> &wgpu::RenderPipeline {
-        let format = self.config.format;
-        let recreate = self
-            .present_pipeline
-            .as_ref()
-            .map_or(true, |pipeline| pipeline.format != format);
-
-        if recreate {
-            let shader = self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: Some("rvello-present-shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("present.wgsl").into()),
-            });
-            let pipeline_layout = self.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("rvello-present-pipeline-layout"),
-                bind_group_layouts: &[&self.present_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-            let pipeline = self.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("rvello-present-pipeline"),
-                layout: Some(&pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: Some("vs_main"),
-                    buffers: &[],
-                    compilation_options: Default::default(),
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: Some("fs_main"),
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format,
-                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                    compilation_options: Default::default(),
-                }),
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    ..Default::default()
-                },
-                depth_stencil: None,
-                multisample: wgpu::MultisampleState::default(),
-                multiview: None,
-                cache: None,
-            });
-            self.present_pipeline = Some(PresentPipeline { pipeline, format });
-        }
+    fn encode_ops(&mut self, bytes: &[u8]) -> Result<Vec<String>, String> {
+        let mut decoder = Decoder::new(bytes);
 
-        &self.present_pipeline.as_ref().unwrap().pipeline
-    }
-}
+        while let Some(op) = decoder.next_opcode()? {
+            match op {
+                OpCode::BeginFrame => {
+                    let logical_width = decoder.read_f32()?;
+                    let logical_height = decoder.read_f32()?;
+                    let dpr = decoder.read_f32()?;
+                    self.width = (logical_width * dpr).round().clamp(1.0, f32::MAX) as u32;
+                    self.height = (logical_height * dpr).round().clamp(1.0, f32::MAX) as u32;
+                    let r = decoder.read_f32()?;
+                    let g = decoder.read_f32()?;
+                    let b = decoder.read_f32()?;
+                    let a = decoder.read_f32()?;
+                    self.base_color = Color::new([r, g, b, a]);
+                }
+                OpCode::Rect => {
+                    let opacity = decoder.read_f32()?;
+                    let transform = decoder.read_mat3()?;
+                    let ox = decoder.read_f32()?;
+                    let oy = decoder.read_f32()?;
+                    let width = decoder.read_f32()?;
+                    let height = decoder.read_f32()?;
+                    let radii = decoder.read_corner_radii()?;
+                    let fill_paint = decoder
+                        .read_optional_paint()?
+                        .map(|paint| paint.with_opacity(opacity));
+                    let stroke_info = decoder
+                        .read_optional_stroke()?
+                        .map(|(stroke, paint)| (stroke, paint.with_opacity(opacity)));
+
+                    let rect = Rect::new(ox as f64, oy as f64, (ox + width) as f64, (oy + height) as f64);
+                    let affine = affine_from_mat3(transform);
+                    match rect_shape(rect, radii) {
+                        RectShape::Rounded(shape) => {
+                            self.fill_and_stroke(Fill::NonZero, affine, fill_paint, stroke_info, &shape)
+                        }
+                        RectShape::Elliptical(shape) => {
+                            self.fill_and_stroke(Fill::NonZero, affine, fill_paint, stroke_info, &shape)
+                        }
+                    }
+                }
+                OpCode::Path => {
+                    let opacity = decoder.read_f32()?;
+                    let transform = decoder.read_mat3()?;
+                    let fill_rule = decoder.read_u8()?;
 
-fn leak_surface(surface: wgpu::Surface<'_>) -> wgpu::Surface<'static> {
-    unsafe { mem::transmute(surface) }
-}
+                    let affine = affine_from_mat3(transform);
 
-fn select_storage_format(adapter: &wgpu::Adapter) -> Option<wgpu::TextureFormat> {
-    const PREFERRED: &[wgpu::TextureFormat] = &[
-        wgpu::TextureFormat::Rgba8Unorm,
-        wgpu::TextureFormat::Rgba8UnormSrgb,
-        wgpu::TextureFormat::Bgra8Unorm,
-        wgpu::TextureFormat::Bgra8UnormSrgb,
-    ];
+                    let fill_paint = decoder
+                        .read_optional_paint()?
+                        .map(|paint| paint.with_opacity(opacity));
+                    let stroke_info = decoder
+                        .read_optional_stroke()?
+                        .map(|(stroke, paint)| (stroke, paint.with_opacity(opacity)));
 
-    for format in PREFERRED {
+                    let Some(bez_path) = decoder.read_path()? else {
+                        continue;
+                    };
+                    let fill_style = if fill_rule == 1 {
+                        Fill::EvenOdd
+                    } else {
+                        Fill::NonZero
+                    };
+
+                    self.fill_and_stroke(fill_style, affine, fill_paint, stroke_info, &bez_path);
+                }
+                opcode @ (OpCode::Text | OpCode::RichText) => {
+                    let op = if matches!(opcode, OpCode::Text) {
+                        decoder.read_text()?
+                    } else {
+                        decoder.read_rich_text()?
+                    };
+                    if op.text.is_empty() {
+                        continue;
+                    }
+
+                    let (fonts, layout) = self.layout_text(&op)?;
+                    let affine = affine_from_mat3(op.transform);
+                    // Glyphs, bounds and decorations are in the layout's line frame.
+                    let frame = Affine::translate((op.ox as f64, op.oy as f64)) * layout.frame();
+                    let transform = affine * frame;
+
+                    let mut runs = Vec::new();
+                    for placed in &layout.lines {
+                        placed.line.push_glyphs(&mut runs, placed.x, placed.baseline);
+                    }
+                    if runs.is_empty() {
+                        continue;
+                    }
+                    let bounds = layout.bounds();
+                    let font_size = op.spans.iter().map(|span| span.font_size).fold(0.0, f32::max);
+
+                    let max_width = op.options.max_width;
+                    let clip =
+                        op.options.overflow == TextOverflow::Clip && max_width.is_finite() && max_width > 0.0;
+                    if clip {
+                        let clip_rect = Rect::new(0.0, bounds.y0, max_width as f64, bounds.y1)
+                            .inflate(0.0, font_size as f64);
+                        self.scene.push_clip_layer(transform, &clip_rect);
+                    }
+
+                    // Underlines and overlines are drawn beneath the glyphs, line-throughs over them.
+                    let (over, under): (Vec<_>, Vec<_>) = layout
+                        .decorations
+                        .iter()
+                        .partition(|line| line.kind == DecorationKind::LineThrough);
+                    for line in under {
+                        self.draw_decoration(&op.spans[line.span], line, affine, frame);
+                    }
+                    let margin = op.glyph_margin();
+                    let bounds = bounds.inflate(margin, margin);
+                    self.draw_glyph_runs(&op, &fonts, runs, affine, frame, bounds);
+                    for line in over {
+                        self.draw_decoration(&op.spans[line.span], line, affine, frame);
+                    }
+
+                    if clip {
+                        self.scene.pop_layer();
+                    }
+                }
+                OpCode::TextPath => {
+                    let path = decoder.read_path()?;
+                    let start_offset = decoder.read_f32()?;
+                    let side = PathSide::from_u8(decoder.read_u8()?);
+                    let mut op = match decoder.next_opcode()? {
+                        Some(OpCode::Text) => decoder.read_text()?,
+                        Some(OpCode::RichText) => decoder.read_rich_text()?,
+                        _ => return Err("Expected a Text or RichText opcode after TextPath".to_string()),
+                    };
+                    let Some(path) = path else {
+                        continue;
+                    };
+                    if op.text.is_empty() {
+                        continue;
+                    }
+                    // Text on a path is set as unwrapped horizontal lines, without decorations.
+                    op.options.max_width = 0.0;
+                    op.options.writing_mode = WritingMode::HorizontalTb;
+
+                    let (fonts, layout) = self.layout_text(&op)?;
+                    let path = TextPath::new(&path, side);
+                    let (runs, bounds) = glyph_runs_on_path(&layout, &path, start_offset, op.align);
+                    if runs.is_empty() {
+                        continue;
+                    }
+                    let affine = affine_from_mat3(op.transform);
+                    let frame = Affine::translate((op.ox as f64, op.oy as f64));
+                    let margin = op.glyph_margin();
+                    let bounds = bounds.inflate(margin, margin);
+                    self.draw_glyph_runs(&op, &fonts, runs, affine, frame, bounds);
+                }
+                OpCode::Image => {
+                    let opacity = decoder.read_f32()?;
+                    let transform = decoder.read_mat3()?;
+                    let ox = decoder.read_f32()? as f64;
+                    let oy = decoder.read_f32()? as f64;
+                    let width = decoder.read_f32()? as f64;
+                    let height = decoder.read_f32()? as f64;
+                    let fit = ImageFit::from_u8(decoder.read_u8()?);
+                    let image_id = decoder.read_u32()?;
+
+                    // Images are uploaded asynchronously, so frames may reference ids that
+                    // have not been registered yet.
+                    let Some(image) = self.images.get(&image_id) else {
+                        continue;
+                    };
+                    let image_size = (image.width as f64, image.height as f64);
+                    let dest = if width > 0.0 && height > 0.0 {
+                        Rect::new(ox, oy, ox + width, oy + height)
+                    } else {
+                        Rect::from_origin_size((ox, oy), image_size)
+                    };
+
+                    let affine = affine_from_mat3(transform);
+                    let image_transform = affine * fit.placement(dest, image_size);
+                    let opacity = normalize_opacity(opacity);
+                    let brush = ImageBrush {
+                        image,
+                        sampler: ImageSampler::new().with_alpha(opacity),
+                    };
+
+                    if matches!(fit, ImageFit::Cover | ImageFit::None) {
+                        self.scene.push_clip_layer(affine, &dest);
+                        self.scene.draw_image(brush, image_transform);
+                        self.scene.pop_layer();
+                    } else {
+                        self.scene.draw_image(brush, image_transform);
+                    }
+                }
+                OpCode::PushLayer => {
+                    let alpha = decoder.read_f32()?;
+                    let blend = decoder.read_blend_mode()?;
+                    let transform = decoder.read_mat3()?;
+                    let clip = decoder.read_clip_shape()?;
+                    decoder.push_layer(LayerKind::Layer);
+
+                    let alpha = normalize_opacity(alpha);
+                    let style = LayerStyle::Blend(blend, alpha);
+                    self.push_layer(style, affine_from_mat3(transform), &clip);
+                }
+                OpCode::PushClip => {
+                    let transform = decoder.read_mat3()?;
+                    let clip = decoder.read_clip_shape()?;
+                    decoder.push_layer(LayerKind::Clip);
+
+                    self.push_layer(LayerStyle::Clip, affine_from_mat3(transform), &clip);
+                }
+                OpCode::PushMask => {
+                    let mode = decoder.read_u8()?;
+                    let alpha = decoder.read_f32()?;
+                    let transform = decoder.read_mat3()?;
+                    let clip = decoder.read_clip_shape()?;
+                    decoder.push_mask()?;
+
+                    let alpha = normalize_opacity(alpha);
+                    // Mask content composites onto the enclosing layer, keeping prior content
+                    // only where the mask covers it.
+                    let style = if mode == 1 {
+                        LayerStyle::LuminanceMask(alpha)
+                    } else {
+                        LayerStyle::Blend(BlendMode::new(Mix::Normal, Compose::DestIn), alpha)
+                    };
+                    self.push_layer(style, affine_from_mat3(transform), &clip);
+                }
+                OpCode::PopLayer => {
+                    decoder.pop_layer()?;
+                    self.scene.pop_layer();
+                }
+                OpCode::EndFrame => break,
+            }
+        }
+
+        decoder.finish()?;
+        Ok(decoder.warnings)
+    }
+
+    fn insert_image(&mut self, id: u32, width: u32, height: u32, pixels: Vec<u8>) {
+        let image = ImageData {
+            data: Blob::new(Arc::new(pixels)),
+            format: ImageFormat::Rgba8,
+            alpha_type: ImageAlphaType::Alpha,
+            width,
+            height,
+        };
+        self.images.insert(id, image);
+    }
+
+    /// Resolves the font chain of every span and lays out the text. The fonts are returned
+    /// alongside the layout, indexed by span, for drawing its glyph runs.
+    fn layout_text(&self, op: &TextOp) -> Result<(Vec<Vec<FontInstance>>, TextLayout), String> {
+        let fonts: Vec<Vec<FontInstance>> = op
+            .spans
+            .iter()
+            .map(|span| self.fonts.resolve_chain(&span.font))
+            .collect();
+        let spans = op
+            .spans
+            .iter()
+            .zip(&fonts)
+            .map(|(span, fonts)| {
+                Ok(TextSpan {
+                    chain: FontChain::new(fonts, span.font_size)?,
+                    range: span.range.clone(),
+                    letter_spacing: span.letter_spacing,
+                    line_height: span.line_height,
+                    decoration: span.decoration,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let layout = layout_text(&op.text, &spans, op.align, &op.options);
+        Ok((fonts, layout))
+    }
+
+    /// Draws glyph runs with the font, size, fill and stroke of their span. Strokes drawn
+    /// beneath the fill go first for every span, so a halo never covers a neighbouring span's
+    /// fill. Color glyphs are drawn with their own paints after their span's fill and never
+    /// stroked. Runs are positioned in the line `frame` of the layout, and `bounds` covers
+    /// every glyph in that frame and is filled by non-solid paints.
+    fn draw_glyph_runs(
+        &mut self,
+        op: &TextOp,
+        fonts: &[Vec<FontInstance>],
+        runs: Vec<GlyphRun>,
+        affine: Affine,
+        frame: Affine,
+        bounds: Rect,
+    ) {
+        let spans = &op.spans;
+        let transform = affine * frame;
+        let draw_run = |scene: &mut Scene, run: &GlyphRun, color: Color, style: StyleRef<'_>| {
+            let instance = &fonts[run.span][run.face];
+            let coords: Vec<NormalizedCoord> =
+                instance.coords().iter().map(|coord| coord.to_bits()).collect();
+            // Upright glyphs in vertical text are turned back against the frame's quarter turn.
+            let glyph_transform = match (run.upright, instance.glyph_transform()) {
+                (false, glyph_transform) => glyph_transform,
+                (true, skew) => {
+                    let upright = Affine::new([0.0, 1.0, -1.0, 0.0, 0.0, 0.0]);
+                    Some(upright * skew.unwrap_or(Affine::IDENTITY))
+                }
+            };
+            scene
+                .draw_glyphs(&instance.font.data)
+                .font_size(spans[run.span].font_size)
+                .transform(transform * run.transform)
+                .glyph_transform(glyph_transform)
+                .normalized_coords(&coords)
+                .brush(color)
+                .draw(style, run.glyphs.iter().copied());
+        };
+        // Outline width a run gains from synthetic bold.
+        let embolden =
+            |run: &GlyphRun| (fonts[run.span][run.face].embolden * spans[run.span].font_size) as f64;
+        // Draws the runs of a span filled, or stroked with `stroke`, widened by any synthetic
+        // bold.
+        let draw = |scene: &mut Scene, runs: &[&GlyphRun], stroke: Option<&Stroke>, paint: &Paint| {
+            let draw_runs = |scene: &mut Scene, color: Color| {
+                for run in runs {
+                    match stroke {
+                        Some(stroke) => {
+                            let mut widened = stroke.clone();
+                            widened.width += embolden(run);
+                            draw_run(scene, run, color, (&widened).into());
+                        }
+                        None => {
+                            draw_run(scene, run, color, Fill::NonZero.into());
+                            if embolden(run) > 0.0 {
+                                draw_run(scene, run, color, (&Stroke::new(embolden(run))).into());
+                            }
+                        }
+                    }
+                }
+            };
+            let outline = stroke.is_none() && runs.iter().any(|run| embolden(run) > 0.0);
+            match paint.brush {
+                Brush::Solid(color) if !outline => draw_runs(scene, color),
+                _ => {
+                    // Glyph runs position their brush per glyph, so gradients are painted over
+                    // the text bounds and kept only where the glyphs cover them. Synthetic bold
+                    // fills are masked the same way so their outline does not double the alpha.
+                    scene.push_layer(BlendMode::default(), 1.0, transform, &bounds);
+                    draw_runs(scene, Color::BLACK);
+                    scene.push_layer(Compose::SrcIn, 1.0, transform, &bounds);
+                    let brush_transform = paint.brush_transform(frame);
+                    scene.fill(Fill::NonZero, transform, &paint.brush, brush_transform, &bounds);
+                    scene.pop_layer();
+                    scene.pop_layer();
+                }
+            }
+        };
+        // Color glyphs ignore the brush except for layers in the foreground color, and
+        // their palette colors and bitmaps take the text opacity from a layer instead.
+        let draw_color = |scene: &mut Scene, run: &GlyphRun| {
+            if op.opacity <= 0.0 {
+                return;
+            }
+            let paint = &spans[run.span].paint;
+            let foreground = paint
+                .fill
+                .iter()
+                .chain(paint.stroke.as_ref().map(|(_, paint)| paint))
+                .find_map(|paint| match paint.brush {
+                    Brush::Solid(color) => Some(color.multiply_alpha(op.opacity.recip())),
+                    _ => None,
+                })
+                .unwrap_or(Color::BLACK);
+            if op.opacity < 1.0 {
+                scene.push_layer(BlendMode::default(), op.opacity, transform, &bounds);
+            }
+            draw_run(scene, run, foreground, Fill::NonZero.into());
+            if op.opacity < 1.0 {
+                scene.pop_layer();
+            }
+        };
+
+        // Runs are drawn a span at a time, so masked paints push their layers once per span
+        // rather than once per run.
+        let mut outline_runs: Vec<Vec<&GlyphRun>> = vec![Vec::new(); spans.len()];
+        let mut color_runs: Vec<Vec<&GlyphRun>> = vec![Vec::new(); spans.len()];
+        for run in &runs {
+            if run.color {
+                color_runs[run.span].push(run);
+            } else {
+                outline_runs[run.span].push(run);
+            }
+        }
+        let strokes = |stroke_first: bool| {
+            spans
+                .iter()
+                .zip(&outline_runs)
+                .filter(|(_, runs)| !runs.is_empty())
+                .filter_map(move |(span, runs)| {
+                    let paint = &span.paint;
+                    let (stroke, stroke_paint) = paint.stroke.as_ref()?;
+                    (paint.stroke_first == stroke_first).then_some((runs.as_slice(), stroke, stroke_paint))
+                })
+        };
+        for (runs, stroke, paint) in strokes(true) {
+            draw(&mut self.scene, runs, Some(stroke), paint);
+        }
+        for ((span, runs), color_runs) in spans.iter().zip(&outline_runs).zip(&color_runs) {
+            if let Some(fill) = span.paint.fill.as_ref().filter(|_| !runs.is_empty()) {
+                draw(&mut self.scene, runs, None, fill);
+            }
+            for run in color_runs {
+                draw_color(&mut self.scene, run);
+            }
+        }
+        for (runs, stroke, paint) in strokes(false) {
+            draw(&mut self.scene, runs, Some(stroke), paint);
+        }
+    }
+
+    /// Draws a decoration line positioned in the layout's line `frame`. Dashes and waves are
+    /// phased by their x position so the pattern continues across gaps.
+    fn draw_decoration(&mut self, span: &TextSpanOp, line: &DecorationLine, affine: Affine, frame: Affine) {
+        let text_paint = &span.paint;
+        let Some(paint) = span
+            .decoration_paint
+            .as_ref()
+            .or(text_paint.fill.as_ref())
+            .or(text_paint.stroke.as_ref().map(|(_, paint)| paint))
+        else {
+            return;
+        };
+        let transform = affine * frame;
+        let brush_transform = paint.brush_transform(frame);
+        let thickness = line.thickness as f64;
+        let y = line.y as f64;
+        let x0 = line.x0 as f64;
+        let x1 = line.x1 as f64;
+        match line.style {
+            DecorationStyle::Solid => {
+                let rect = Rect::new(x0, y - thickness * 0.5, x1, y + thickness * 0.5);
+                self.scene
+                    .fill(Fill::NonZero, transform, &paint.brush, brush_transform, &rect);
+            }
+            DecorationStyle::Dashed => {
+                let dash = thickness * 3.0;
+                let stroke = Stroke::new(thickness).with_dashes(x0.rem_euclid(dash * 2.0), [dash, dash]);
+                let line = Line::new((x0, y), (x1, y));
+                self.scene
+                    .stroke(&stroke, transform, &paint.brush, brush_transform, &line);
+            }
+            DecorationStyle::Wavy => {
+                let wavelength = (thickness * 6.0).max(4.0);
+                let amplitude = thickness.max(1.0);
+                let step = wavelength / 16.0;
+                let wave = |x: f64| Point::new(x, y + amplitude * (x / wavelength * 2.0 * PI).sin());
+                let mut path = BezPath::new();
+                path.move_to(wave(x0));
+                let mut x = (x0 / step).floor() * step + step;
+                while x < x1 {
+                    path.line_to(wave(x));
+                    x += step;
+                }
+                path.line_to(wave(x1));
+                let stroke = Stroke::new(thickness);
+                self.scene
+                    .stroke(&stroke, transform, &paint.brush, brush_transform, &path);
+            }
+        }
+    }
+
+    fn fill_and_stroke(
+        &mut self,
+        fill: Fill,
+        transform: Affine,
+        fill_paint: Option<Paint>,
+        stroke_info: Option<(Stroke, Paint)>,
+        shape: &impl Shape,
+    ) {
+        if let Some(paint) = fill_paint {
+            self.scene
+                .fill(fill, transform, &paint.brush, paint.transform, shape);
+        }
+
+        if let Some((stroke, paint)) = stroke_info {
+            self.scene
+                .stroke(&stroke, transform, &paint.brush, paint.transform, shape);
+        }
+    }
+
+    fn push_layer(&mut self, style: LayerStyle, transform: Affine, clip: &ClipShape) {
+        match clip {
+            ClipShape::None => {
+                let viewport = Rect::new(0.0, 0.0, self.width as f64, self.height as f64);
+                push_shape_layer(&mut self.scene, style, Affine::IDENTITY, &viewport);
+            }
+            ClipShape::Rect(rect) => push_shape_layer(&mut self.scene, style, transform, rect),
+            ClipShape::RoundedRect(rect) => push_shape_layer(&mut self.scene, style, transform, rect),
+            ClipShape::Path { path, fill } => match fill {
+                Fill::NonZero => push_shape_layer(&mut self.scene, style, transform, path),
+                Fill::EvenOdd => push_even_odd_layer(&mut self.scene, style, transform, path),
+            },
+        }
+    }
+}
+
+impl RendererHandle {
+    fn ensure_offscreen_target(&mut self) -> &OffscreenTarget {
+        let needs_recreate = self
+            .offscreen
+            .as_ref()
+            .is_none_or(|target| target.width != self.config.width || target.height != self.config.height);
+
+        if needs_recreate {
+            let format = self.storage_format;
+            let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("rvello-offscreen-texture"),
+                size: wgpu::Extent3d {
+                    width: self.config.width.max(1),
+                    height: self.config.height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            self.offscreen = Some(OffscreenTarget {
+                texture,
+                view,
+                width: self.config.width,
+                height: self.config.height,
+            });
+            self.present_bind_group = None;
+        }
+
+        self.offscreen.as_ref().unwrap()
+    }
+
+    fn ensure_present_bind_group(&mut self, view: &wgpu::TextureView) -> &wgpu::BindGroup {
+        if self.present_bind_group.is_none() {
+            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("rvello-present-bind-group"),
+                layout: &self.present_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                ],
+            });
+            self.present_bind_group = Some(bind_group);
+        }
+        self.present_bind_group.as_ref().unwrap()
+    }
+
+    fn ensure_present_pipeline(&mut self) -> &wgpu::RenderPipeline {
+        let format = self.config.format;
+        let recreate = self
+            .present_pipeline
+            .as_ref()
+            .is_none_or(|pipeline| pipeline.format != format);
+
+        if recreate {
+            let shader = self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("rvello-present-shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("present.wgsl").into()),
+            });
+            let pipeline_layout = self.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("rvello-present-pipeline-layout"),
+                bind_group_layouts: &[&self.present_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+            let pipeline = self.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("rvello-present-pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            });
+            self.present_pipeline = Some(PresentPipeline { pipeline, format });
+        }
+
+        &self.present_pipeline.as_ref().unwrap().pipeline
+    }
+}
+
+/// Surface target drawing into `canvas`. Only wasm32 builds have canvases; native builds,
+/// such as unit tests, cannot create a renderer.
+#[cfg(target_arch = "wasm32")]
+fn canvas_target(canvas: &HtmlCanvasElement) -> Result<wgpu::SurfaceTarget<'static>, JsValue> {
+    Ok(wgpu::SurfaceTarget::Canvas(canvas.clone()))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn canvas_target(_canvas: &HtmlCanvasElement) -> Result<wgpu::SurfaceTarget<'static>, JsValue> {
+    Err(js_error("WebGPU canvases are only available in wasm32 builds"))
+}
+
+fn push_shape_layer(scene: &mut Scene, style: LayerStyle, transform: Affine, clip: &impl Shape) {
+    match style {
+        LayerStyle::Blend(blend, alpha) => scene.push_layer(blend, alpha, transform, clip),
+        LayerStyle::Clip => scene.push_clip_layer(transform, clip),
+        LayerStyle::LuminanceMask(alpha) => scene.push_luminance_mask_layer(alpha, transform, clip),
+    }
+}
+
+/// `Scene` always encodes layer shapes with the non-zero rule, so even-odd clip paths
+/// are written to the encoding directly.
+fn push_even_odd_layer(scene: &mut Scene, style: LayerStyle, transform: Affine, clip: &BezPath) {
+    let parameters = match style {
+        LayerStyle::Blend(blend, alpha) => DrawBeginClip::new(blend, alpha),
+        LayerStyle::Clip => DrawBeginClip::clip(),
+        LayerStyle::LuminanceMask(alpha) => DrawBeginClip::luminance_mask(alpha),
+    };
+    let encoding = scene.encoding_mut();
+    encoding.encode_transform(Transform::from_kurbo(&transform));
+    encoding.encode_fill_style(Fill::EvenOdd);
+    if !encoding.encode_shape(clip, true) {
+        // Matches `Scene::push_layer`: an invalid shape suppresses drawing until the pop.
+        encoding.encode_empty_shape();
+    }
+    encoding.encode_begin_clip(parameters);
+}
+
+fn leak_surface(surface: wgpu::Surface<'_>) -> wgpu::Surface<'static> {
+    unsafe { mem::transmute(surface) }
+}
+
+fn select_storage_format(adapter: &wgpu::Adapter) -> Option<wgpu::TextureFormat> {
+    const PREFERRED: &[wgpu::TextureFormat] = &[
+        wgpu::TextureFormat::Rgba8Unorm,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+        wgpu::TextureFormat::Bgra8Unorm,
+        wgpu::TextureFormat::Bgra8UnormSrgb,
+    ];
+
+    for format in PREFERRED {
         if format_supports_storage(*format, adapter) {
             return Some(*format);
         }
@@ -659,67 +1174,342 @@ fn select_alpha_mode(modes: &[wgpu::CompositeAlphaMode]) -> wgpu::CompositeAlpha
         .unwrap_or(wgpu::CompositeAlphaMode::Auto)
 }
 
-fn js_error(message: &str) -> JsValue {
-    JsValue::from_str(message)
+fn set_property(target: &Object, key: &str, value: &JsValue) -> Result<(), JsValue> {
+    Reflect::set(target, &JsValue::from_str(key), value).map(|_| ())
+}
+
+fn rect_object(rect: Rect) -> Result<JsValue, JsValue> {
+    let object = Object::new();
+    set_property(&object, "x", &rect.x0.into())?;
+    set_property(&object, "y", &rect.y0.into())?;
+    set_property(&object, "width", &rect.width().into())?;
+    set_property(&object, "height", &rect.height().into())?;
+    Ok(object.into())
+}
+
+/// Decodes a buffer holding a single `Text` or `RichText` opcode.
+fn decode_text_op(op: Uint8Array) -> Result<TextOp, JsValue> {
+    let bytes = op.to_vec();
+    let mut decoder = Decoder::new(&bytes);
+    let op = match decoder.next_opcode()? {
+        Some(OpCode::Text) => decoder.read_text()?,
+        Some(OpCode::RichText) => decoder.read_rich_text()?,
+        _ => return Err(js_error("Expected a Text or RichText opcode")),
+    };
+    Ok(op)
+}
+
+/// Converts a UTF-16 offset, as used by JavaScript strings, to a byte offset into `text`,
+/// rounding down to a character boundary.
+fn byte_index(text: &str, index: u32) -> usize {
+    let mut units = 0;
+    for (offset, ch) in text.char_indices() {
+        units += ch.len_utf16();
+        if units > index as usize {
+            return offset;
+        }
+    }
+    text.len()
+}
+
+/// Converts a byte offset into `text` to a UTF-16 offset.
+fn utf16_index(text: &str, offset: usize) -> u32 {
+    text.get(..offset).unwrap_or(text).encode_utf16().count() as u32
+}
+
+fn js_error(message: &str) -> JsValue {
+    JsValue::from_str(message)
+}
+
+#[derive(Copy, Clone)]
+enum ImageFit {
+    Stretch,
+    Contain,
+    Cover,
+    /// Natural size, centred and clipped like `Cover`.
+    None,
+}
+
+impl ImageFit {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => ImageFit::Contain,
+            2 => ImageFit::Cover,
+            3 => ImageFit::None,
+            _ => ImageFit::Stretch,
+        }
+    }
+
+    /// Maps an image of `image_size` pixels into `dest`, centring it when the fit keeps its
+    /// aspect ratio.
+    fn placement(self, dest: Rect, image_size: (f64, f64)) -> Affine {
+        let (image_width, image_height) = image_size;
+        let (scale_x, scale_y) = match self {
+            ImageFit::Stretch => (dest.width() / image_width, dest.height() / image_height),
+            ImageFit::Contain => {
+                let scale = (dest.width() / image_width).min(dest.height() / image_height);
+                (scale, scale)
+            }
+            ImageFit::Cover => {
+                let scale = (dest.width() / image_width).max(dest.height() / image_height);
+                (scale, scale)
+            }
+            ImageFit::None => (1.0, 1.0),
+        };
+        let offset_x = dest.x0 + (dest.width() - image_width * scale_x) * 0.5;
+        let offset_y = dest.y0 + (dest.height() - image_height * scale_y) * 0.5;
+        Affine::translate((offset_x, offset_y)) * Affine::scale_non_uniform(scale_x, scale_y)
+    }
+}
+
+enum RectShape {
+    Rounded(RoundedRect),
+    Elliptical(BezPath),
+}
+
+/// Builds a rect with per-corner radii, using `RoundedRect` when every corner is circular
+/// and a path of elliptical arcs otherwise.
+fn rect_shape(rect: Rect, radii: [Vec2; 4]) -> RectShape {
+    let rect = rect.abs();
+    let radii = normalize_corner_radii(rect, radii);
+    if radii.iter().all(|radius| radius.x == radius.y) {
+        let [top_left, top_right, bottom_right, bottom_left] = radii.map(|radius| radius.x);
+        let radii = RoundedRectRadii::new(top_left, top_right, bottom_right, bottom_left);
+        return RectShape::Rounded(RoundedRect::from_rect(rect, radii));
+    }
+
+    let [top_left, top_right, bottom_right, bottom_left] = radii;
+    let mut path = BezPath::new();
+    path.move_to((rect.x0 + top_left.x, rect.y0));
+    path.line_to((rect.x1 - top_right.x, rect.y0));
+    let center = Point::new(rect.x1 - top_right.x, rect.y0 + top_right.y);
+    append_corner(&mut path, center, top_right, -FRAC_PI_2);
+    path.line_to((rect.x1, rect.y1 - bottom_right.y));
+    let center = Point::new(rect.x1 - bottom_right.x, rect.y1 - bottom_right.y);
+    append_corner(&mut path, center, bottom_right, 0.0);
+    path.line_to((rect.x0 + bottom_left.x, rect.y1));
+    let center = Point::new(rect.x0 + bottom_left.x, rect.y1 - bottom_left.y);
+    append_corner(&mut path, center, bottom_left, FRAC_PI_2);
+    path.line_to((rect.x0, rect.y0 + top_left.y));
+    let center = Point::new(rect.x0 + top_left.x, rect.y0 + top_left.y);
+    append_corner(&mut path, center, top_left, PI);
+    path.close_path();
+    RectShape::Elliptical(path)
+}
+
+/// Drops corners with a zero or invalid radius and scales all radii down together when
+/// adjacent corners would overlap, as CSS `border-radius` does.
+fn normalize_corner_radii(rect: Rect, radii: [Vec2; 4]) -> [Vec2; 4] {
+    let radii = radii.map(|radius| {
+        let valid = radius.x.is_finite() && radius.y.is_finite() && radius.x > 0.0 && radius.y > 0.0;
+        if valid {
+            radius
+        } else {
+            Vec2::ZERO
+        }
+    });
+    let [top_left, top_right, bottom_right, bottom_left] = radii;
+    let scale = [
+        rect.width() / (top_left.x + top_right.x),
+        rect.width() / (bottom_left.x + bottom_right.x),
+        rect.height() / (top_left.y + bottom_left.y),
+        rect.height() / (top_right.y + bottom_right.y),
+    ]
+    .into_iter()
+    .filter(|scale| scale.is_finite())
+    .fold(1.0, f64::min);
+    radii.map(|radius| radius * scale)
+}
+
+/// Appends a quarter ellipse around `center`, starting at `start_angle` and sweeping clockwise.
+fn append_corner(path: &mut BezPath, center: Point, radii: Vec2, start_angle: f64) {
+    if radii == Vec2::ZERO {
+        path.line_to(center);
+        return;
+    }
+    let arc = vello::kurbo::Arc::new(center, radii, start_angle, FRAC_PI_2, 0.0);
+    path.extend(arc.append_iter(0.1));
 }
 
+/// How a pushed layer composites its content with what is underneath.
 #[derive(Copy, Clone)]
-enum TextAlign {
-    Start,
-    Center,
-    End,
+enum LayerStyle {
+    Blend(BlendMode, f32),
+    /// Clips subsequent draws without isolating them for blending.
+    Clip,
+    /// Uses the luminance of subsequent draws as a mask for the enclosing layer.
+    LuminanceMask(f32),
 }
 
-impl TextAlign {
-    fn from_u8(value: u8) -> Self {
-        match value {
-            1 => TextAlign::Center,
-            2 => TextAlign::End,
-            _ => TextAlign::Start,
+#[derive(Copy, Clone, PartialEq)]
+enum LayerKind {
+    Layer,
+    Clip,
+    Mask,
+}
+
+/// Clip shape attached to a layer, in the coordinate space of the layer transform.
+enum ClipShape {
+    /// Unclipped; the layer covers the whole viewport.
+    None,
+    Rect(Rect),
+    RoundedRect(RoundedRect),
+    Path {
+        path: BezPath,
+        fill: Fill,
+    },
+}
+
+/// A decoded brush together with its gradient-space transform, which is relative to the
+/// transform of the shape it paints.
+struct Paint {
+    brush: Brush,
+    transform: Option<Affine>,
+}
+
+impl Paint {
+    fn with_opacity(self, opacity: f32) -> Self {
+        Self {
+            brush: self.brush.multiply_alpha(normalize_opacity(opacity)),
+            transform: self.transform,
+        }
+    }
+
+    /// Brush transform that keeps the paint in text coordinates for shapes drawn in `frame`.
+    fn brush_transform(&self, frame: Affine) -> Option<Affine> {
+        if frame == Affine::IDENTITY {
+            return self.transform;
         }
+        Some(frame.inverse() * self.transform.unwrap_or(Affine::IDENTITY))
     }
 }
 
-struct LineLayout {
+/// A decoded `Text` or `RichText` opcode, laid out identically for drawing and measurement.
+/// A `Text` opcode decodes to a single span covering all of `text`.
+struct TextOp {
+    /// Element opacity, already applied to the span paints.
+    opacity: f32,
+    transform: [f32; 6],
+    ox: f32,
+    oy: f32,
+    align: TextAlign,
+    options: TextLayoutOptions,
     text: String,
-    width: f32,
+    spans: Vec<TextSpanOp>,
+}
+
+impl TextOp {
+    /// Margin around the line boxes that covers glyphs overhanging them and the widest stroke.
+    fn glyph_margin(&self) -> f64 {
+        let font_size = self.spans.iter().map(|span| span.font_size).fold(0.0, f32::max);
+        let stroke_width = self
+            .spans
+            .iter()
+            .filter_map(|span| span.paint.stroke.as_ref())
+            .map(|(stroke, _)| stroke.width)
+            .fold(0.0, f64::max);
+        font_size as f64 + stroke_width
+    }
+}
+
+/// How the glyphs of a text span are filled and stroked.
+struct TextPaint {
+    fill: Option<Paint>,
+    stroke: Option<(Stroke, Paint)>,
+    /// Draws the stroke beneath the fill, like SVG `paint-order: stroke`, for halos.
+    stroke_first: bool,
+}
+
+/// Style of a byte range of a `TextOp`.
+struct TextSpanOp {
+    range: Range<usize>,
+    font: FontQuery,
+    font_size: f32,
+    line_height: f32,
+    letter_spacing: f32,
+    paint: TextPaint,
+    decoration: TextDecoration,
+    /// Paint of the decoration lines; `None` uses the text fill, or its stroke paint when
+    /// the text is only stroked.
+    decoration_paint: Option<Paint>,
 }
 
 struct Decoder<'a> {
     data: &'a [u8],
     offset: usize,
+    layers: Vec<LayerKind>,
+    /// Problems with nodes that were skipped while the rest of the frame decoded.
+    warnings: Vec<String>,
 }
 
 impl<'a> Decoder<'a> {
     fn new(data: &'a [u8]) -> Self {
-        Self { data, offset: 0 }
+        Self {
+            data,
+            offset: 0,
+            layers: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn push_layer(&mut self, kind: LayerKind) {
+        self.layers.push(kind);
+    }
+
+    /// Masks must sit directly inside a `PushLayer` that isolates the content they modulate.
+    /// Clip layers do not isolate, since tiles fully inside a clip skip it, so a mask under
+    /// one would erase whatever was drawn beneath the clip on some tiles and not others.
+    fn push_mask(&mut self) -> Result<(), String> {
+        if self.layers.last() != Some(&LayerKind::Layer) {
+            return Err("PushMask must be directly inside a PushLayer".to_string());
+        }
+        self.layers.push(LayerKind::Mask);
+        Ok(())
+    }
+
+    fn pop_layer(&mut self) -> Result<(), String> {
+        self.layers
+            .pop()
+            .map(|_| ())
+            .ok_or_else(|| "PopLayer without a matching push".to_string())
+    }
+
+    /// Verifies that every pushed layer was popped before the end of the frame.
+    fn finish(&self) -> Result<(), String> {
+        if !self.layers.is_empty() {
+            return Err(format!(
+                "Unbalanced layers: {} pushed layer(s) without a matching PopLayer",
+                self.layers.len()
+            ));
+        }
+        Ok(())
     }
 
     fn remaining(&self) -> usize {
         self.data.len().saturating_sub(self.offset)
     }
 
-    fn next_opcode(&mut self) -> Result<Option<OpCode>, JsValue> {
+    fn next_opcode(&mut self) -> Result<Option<OpCode>, String> {
         if self.remaining() == 0 {
             return Ok(None);
         }
         let byte = self.data[self.offset];
         self.offset += 1;
         OpCode::from_byte(byte)
-            .ok_or_else(|| JsValue::from_str("Unknown opcode"))
+            .ok_or_else(|| "Unknown opcode".to_string())
             .map(Some)
     }
 
-    fn read_f32(&mut self) -> Result<f32, JsValue> {
+    fn read_f32(&mut self) -> Result<f32, String> {
         if self.remaining() < 4 {
-            return Err(JsValue::from_str("Unexpected end of buffer"));
+            return Err("Unexpected end of buffer".to_string());
         }
         let chunk = &self.data[self.offset..self.offset + 4];
         self.offset += 4;
         Ok(f32::from_le_bytes(chunk.try_into().unwrap()))
     }
 
-    fn read_mat3(&mut self) -> Result<[f32; 6], JsValue> {
+    fn read_mat3(&mut self) -> Result<[f32; 6], String> {
         let mut values = [0.0; 6];
         for slot in &mut values {
             *slot = self.read_f32()?;
@@ -727,32 +1517,446 @@ impl<'a> Decoder<'a> {
         Ok(values)
     }
 
-    fn read_u8(&mut self) -> Result<u8, JsValue> {
+    fn read_u8(&mut self) -> Result<u8, String> {
         if self.remaining() < 1 {
-            return Err(JsValue::from_str("Unexpected end of buffer"));
+            return Err("Unexpected end of buffer".to_string());
         }
         let value = self.data[self.offset];
         self.offset += 1;
         Ok(value)
     }
 
-    fn read_u32(&mut self) -> Result<u32, JsValue> {
+    fn read_u32(&mut self) -> Result<u32, String> {
         if self.remaining() < 4 {
-            return Err(JsValue::from_str("Unexpected end of buffer"));
+            return Err("Unexpected end of buffer".to_string());
         }
         let chunk = &self.data[self.offset..self.offset + 4];
         self.offset += 4;
         Ok(u32::from_le_bytes(chunk.try_into().unwrap()))
     }
 
-    fn read_string(&mut self, len: usize) -> Result<String, JsValue> {
+    fn read_color(&mut self) -> Result<Color, String> {
+        let r = self.read_f32()?;
+        let g = self.read_f32()?;
+        let b = self.read_f32()?;
+        let a = self.read_f32()?;
+        Ok(Color::new([r, g, b, a.clamp(0.0, 1.0)]))
+    }
+
+    /// Reads a solid color or a linear, radial (two-point conical) or sweep gradient with
+    /// its extend mode, gradient-space transform and color stops.
+    fn read_paint(&mut self) -> Result<Paint, String> {
+        let kind = self.read_u8()?;
+        let mut gradient = match kind {
+            0 => {
+                let color = self.read_color()?;
+                return Ok(Paint {
+                    brush: Brush::Solid(color),
+                    transform: None,
+                });
+            }
+            1 => {
+                let x0 = self.read_f32()? as f64;
+                let y0 = self.read_f32()? as f64;
+                let x1 = self.read_f32()? as f64;
+                let y1 = self.read_f32()? as f64;
+                Gradient::new_linear((x0, y0), (x1, y1))
+            }
+            2 => {
+                let x0 = self.read_f32()? as f64;
+                let y0 = self.read_f32()? as f64;
+                let r0 = self.read_f32()?;
+                let x1 = self.read_f32()? as f64;
+                let y1 = self.read_f32()? as f64;
+                let r1 = self.read_f32()?;
+                Gradient::new_two_point_radial((x0, y0), r0.max(0.0), (x1, y1), r1.max(0.0))
+            }
+            3 => {
+                let cx = self.read_f32()? as f64;
+                let cy = self.read_f32()? as f64;
+                let start_angle = self.read_f32()?;
+                let end_angle = self.read_f32()?;
+                Gradient::new_sweep((cx, cy), start_angle, end_angle)
+            }
+            _ => return Err("Unknown paint kind".to_string()),
+        };
+
+        gradient.extend = match self.read_u8()? {
+            1 => Extend::Repeat,
+            2 => Extend::Reflect,
+            _ => Extend::Pad,
+        };
+        let transform = affine_from_mat3(self.read_mat3()?);
+        let stop_count = self.read_u32()? as usize;
+        if self.remaining() < stop_count.saturating_mul(20) {
+            return Err("Unexpected end of buffer".to_string());
+        }
+        let mut stops = Vec::with_capacity(stop_count);
+        for _ in 0..stop_count {
+            let offset = self.read_f32()?;
+            let color = self.read_color()?;
+            stops.push(ColorStop::from((offset.clamp(0.0, 1.0), color)));
+        }
+        stops.sort_by(|a, b| a.offset.total_cmp(&b.offset));
+
+        Ok(Paint {
+            brush: Brush::Gradient(gradient.with_stops(stops.as_slice())),
+            transform: Some(transform),
+        })
+    }
+
+    /// Reads a presence flag followed by a paint when the flag is set.
+    fn read_optional_paint(&mut self) -> Result<Option<Paint>, String> {
+        if self.read_u8()? == 0 {
+            return Ok(None);
+        }
+        self.read_paint().map(Some)
+    }
+
+    /// Reads a presence flag followed by a stroke style and its paint when the flag is set.
+    fn read_optional_stroke(&mut self) -> Result<Option<(Stroke, Paint)>, String> {
+        if self.read_u8()? == 0 {
+            return Ok(None);
+        }
+        let width = self.read_f32()?;
+        let join = match self.read_u8()? {
+            1 => Join::Bevel,
+            2 => Join::Round,
+            _ => Join::Miter,
+        };
+        let cap = match self.read_u8()? {
+            1 => Cap::Square,
+            2 => Cap::Round,
+            _ => Cap::Butt,
+        };
+        let miter_limit = self.read_f32()?;
+        let dash_count = self.read_u32()? as usize;
+        if self.remaining() < dash_count.saturating_mul(4) {
+            return Err("Unexpected end of buffer".to_string());
+        }
+        let mut dashes = Vec::with_capacity(dash_count);
+        for _ in 0..dash_count {
+            dashes.push(self.read_f32()? as f64);
+        }
+        let dash_offset = self.read_f32()? as f64;
+        let paint = self.read_paint()?;
+
+        let mut stroke = Stroke::new(width.max(0.0) as f64).with_join(join).with_caps(cap);
+        if miter_limit.is_finite() && miter_limit >= 1.0 {
+            stroke = stroke.with_miter_limit(miter_limit as f64);
+        }
+        // Like canvas `setLineDash`, ignore patterns that can't advance and repeat odd-length
+        // patterns to make them even.
+        let valid_dashes = dashes.iter().all(|dash| dash.is_finite() && *dash >= 0.0);
+        if valid_dashes && dashes.iter().sum::<f64>() > 0.0 {
+            if dashes.len() % 2 == 1 {
+                dashes.extend_from_within(..);
+            }
+            let dash_offset = if dash_offset.is_finite() { dash_offset } else { 0.0 };
+            stroke = stroke.with_dashes(dash_offset, dashes);
+        }
+
+        Ok(Some((stroke, paint)))
+    }
+
+    /// Reads the x and y radius of each corner, clockwise from the top-left.
+    fn read_corner_radii(&mut self) -> Result<[Vec2; 4], String> {
+        let mut radii = [Vec2::ZERO; 4];
+        for radius in &mut radii {
+            let x = self.read_f32()? as f64;
+            let y = self.read_f32()? as f64;
+            *radius = Vec2::new(x, y);
+        }
+        Ok(radii)
+    }
+
+    fn read_blend_mode(&mut self) -> Result<BlendMode, String> {
+        let mix = mix_from_u8(self.read_u8()?).ok_or_else(|| "Unknown blend mode".to_string())?;
+        let compose =
+            compose_from_u8(self.read_u8()?).ok_or_else(|| "Unknown compose mode".to_string())?;
+        Ok(BlendMode::new(mix, compose))
+    }
+
+    fn read_clip_shape(&mut self) -> Result<ClipShape, String> {
+        match self.read_u8()? {
+            0 => Ok(ClipShape::None),
+            1 => {
+                let x = self.read_f32()? as f64;
+                let y = self.read_f32()? as f64;
+                let width = self.read_f32()? as f64;
+                let height = self.read_f32()? as f64;
+                Ok(ClipShape::Rect(Rect::new(x, y, x + width, y + height)))
+            }
+            2 => {
+                let x = self.read_f32()? as f64;
+                let y = self.read_f32()? as f64;
+                let width = self.read_f32()? as f64;
+                let height = self.read_f32()? as f64;
+                let radii = self.read_corner_radii()?;
+                match rect_shape(Rect::new(x, y, x + width, y + height), radii) {
+                    RectShape::Rounded(rect) => Ok(ClipShape::RoundedRect(rect)),
+                    RectShape::Elliptical(path) => Ok(ClipShape::Path {
+                        path,
+                        fill: Fill::NonZero,
+                    }),
+                }
+            }
+            3 => {
+                let fill = if self.read_u8()? == 1 {
+                    Fill::EvenOdd
+                } else {
+                    Fill::NonZero
+                };
+                // An invalid clip path clips away all of its content.
+                Ok(match self.read_path()? {
+                    Some(path) => ClipShape::Path { path, fill },
+                    None => ClipShape::Rect(Rect::ZERO),
+                })
+            }
+            _ => Err("Unknown clip shape".to_string()),
+        }
+    }
+
+    /// Reads path data, either as an SVG `d` string or as the binary verb and point arrays.
+    /// Path data that does not describe a valid path is consumed and recorded as a warning,
+    /// returning `None` so the node using it can be skipped.
+    fn read_path(&mut self) -> Result<Option<BezPath>, String> {
+        let path = match self.read_u8()? {
+            0 => {
+                let path_len = self.read_u32()?;
+                let path_str = self.read_string(path_len as usize)?;
+                BezPath::from_svg(&path_str).map_err(|err| format!("Invalid SVG path data: {err}"))
+            }
+            1 => self.read_binary_path()?,
+            _ => return Err("Unknown path encoding".to_string()),
+        };
+        match path {
+            Ok(path) => Ok(Some(path)),
+            Err(warning) => {
+                self.warnings.push(warning);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Reads binary path data, returning an error message for verbs and points that do not
+    /// form a path.
+    fn read_binary_path(&mut self) -> Result<Result<BezPath, String>, String> {
+        let verb_count = self.read_u32()? as usize;
+        if self.remaining() < verb_count {
+            return Err("Unexpected end of buffer".to_string());
+        }
+        let verbs_start = self.offset;
+        self.offset += verb_count;
+
+        let point_count = self.read_u32()? as usize;
+        if self.remaining() / 8 < point_count {
+            return Err("Unexpected end of buffer".to_string());
+        }
+        let points_start = self.offset;
+        self.offset += point_count * 8;
+
+        let verbs = &self.data[verbs_start..verbs_start + verb_count];
+        let points = &self.data[points_start..self.offset];
+        Ok(build_binary_path(verbs, points))
+    }
+
+    fn read_text(&mut self) -> Result<TextOp, String> {
+        let opacity = self.read_f32()?;
+        let transform = self.read_mat3()?;
+        let ox = self.read_f32()?;
+        let oy = self.read_f32()?;
+        let font_size = self.read_f32()?;
+        let line_height = self.read_f32()?;
+        let max_width = self.read_f32()?;
+        let align = TextAlign::from_u8(self.read_u8()?);
+        let letter_spacing = self.read_f32()?;
+        let word_spacing = self.read_f32()?;
+        let options = self.read_text_options(max_width, word_spacing)?;
+        let font = self.read_font()?;
+        let paint = self.read_text_paint(opacity)?;
+        let (decoration, decoration_paint) = self.read_decoration(opacity)?;
+        let text_len = self.read_u32()?;
+        let text = self.read_string(text_len as usize)?;
+
+        let span = TextSpanOp {
+            range: 0..text.len(),
+            font,
+            font_size: normalize_font_size(font_size),
+            line_height,
+            letter_spacing,
+            paint,
+            decoration,
+            decoration_paint,
+        };
+        Ok(TextOp {
+            opacity: normalize_opacity(opacity),
+            transform,
+            ox,
+            oy,
+            align,
+            options,
+            text,
+            spans: vec![span],
+        })
+    }
+
+    fn read_rich_text(&mut self) -> Result<TextOp, String> {
+        let opacity = self.read_f32()?;
+        let transform = self.read_mat3()?;
+        let ox = self.read_f32()?;
+        let oy = self.read_f32()?;
+        let max_width = self.read_f32()?;
+        let align = TextAlign::from_u8(self.read_u8()?);
+        let word_spacing = self.read_f32()?;
+        let options = self.read_text_options(max_width, word_spacing)?;
+        let span_count = self.read_u32()? as usize;
+
+        let mut text = String::new();
+        let mut spans = Vec::with_capacity(span_count.min(self.remaining()));
+        for _ in 0..span_count {
+            let font_size = self.read_f32()?;
+            let line_height = self.read_f32()?;
+            let letter_spacing = self.read_f32()?;
+            let font = self.read_font()?;
+            let paint = self.read_text_paint(opacity)?;
+            let (decoration, decoration_paint) = self.read_decoration(opacity)?;
+            let text_len = self.read_u32()?;
+            let span_text = self.read_string(text_len as usize)?;
+            if span_text.is_empty() {
+                continue;
+            }
+            let start = text.len();
+            text.push_str(&span_text);
+            spans.push(TextSpanOp {
+                range: start..text.len(),
+                font,
+                font_size: normalize_font_size(font_size),
+                line_height,
+                letter_spacing,
+                paint,
+                decoration,
+                decoration_paint,
+            });
+        }
+
+        Ok(TextOp {
+            opacity: normalize_opacity(opacity),
+            transform,
+            ox,
+            oy,
+            align,
+            options,
+            text,
+            spans,
+        })
+    }
+
+    /// Reads the paragraph settings shared by the `Text` and `RichText` opcodes.
+    fn read_text_options(&mut self, max_width: f32, word_spacing: f32) -> Result<TextLayoutOptions, String> {
+        let direction = TextDirection::from_u8(self.read_u8()?);
+        let white_space = WhiteSpace::from_u8(self.read_u8()?);
+        let overflow_wrap = OverflowWrap::from_u8(self.read_u8()?);
+        let max_lines = self.read_u32()?;
+        let overflow_mode = self.read_u8()?;
+        let ellipsis_len = self.read_u32()?;
+        let ellipsis = self.read_string(ellipsis_len as usize)?;
+        let writing_mode = WritingMode::from_u8(self.read_u8()?);
+        let orientation = TextOrientation::from_u8(self.read_u8()?);
+        Ok(TextLayoutOptions {
+            max_width,
+            direction,
+            white_space,
+            overflow_wrap,
+            word_spacing,
+            max_lines: (max_lines > 0).then_some(max_lines as usize),
+            overflow: TextOverflow::new(overflow_mode, ellipsis),
+            writing_mode,
+            orientation,
+        })
+    }
+
+    /// Reads the optional fill and stroke of a text span followed by their paint order.
+    fn read_text_paint(&mut self, opacity: f32) -> Result<TextPaint, String> {
+        let fill = self
+            .read_optional_paint()?
+            .map(|paint| paint.with_opacity(opacity));
+        let stroke = self
+            .read_optional_stroke()?
+            .map(|(stroke, paint)| (stroke, paint.with_opacity(opacity)));
+        let stroke_first = self.read_u8()? == 1;
+        Ok(TextPaint {
+            fill,
+            stroke,
+            stroke_first,
+        })
+    }
+
+    /// Reads the decoration lines of a text span and their optional paint.
+    fn read_decoration(&mut self, opacity: f32) -> Result<(TextDecoration, Option<Paint>), String> {
+        let lines = self.read_u8()?;
+        let style = self.read_u8()?;
+        let thickness = self.read_f32()?;
+        let paint = self
+            .read_optional_paint()?
+            .map(|paint| paint.with_opacity(opacity));
+        Ok((TextDecoration::new(lines, style, thickness), paint))
+    }
+
+    /// Reads a CSS family list, weight and style.
+    fn read_font(&mut self) -> Result<FontQuery, String> {
+        let family_len = self.read_u32()?;
+        let family = self.read_string(family_len as usize)?;
+        let weight = self.read_f32()?;
+        let style = FontStyle::from_u8(self.read_u8()?);
+        let width = self.read_f32()?;
+        let slant = self.read_f32()?;
+        let variation_count = self.read_u32()? as usize;
+        if self.remaining() < variation_count.saturating_mul(8) {
+            return Err("Unexpected end of buffer".to_string());
+        }
+        let mut variations = Vec::with_capacity(variation_count);
+        for _ in 0..variation_count {
+            let tag = [self.read_u8()?, self.read_u8()?, self.read_u8()?, self.read_u8()?];
+            let value = self.read_f32()?;
+            if value.is_finite() {
+                variations.push((Tag::new(&tag), value));
+            }
+        }
+
+        let weight = if weight.is_finite() {
+            weight.round().clamp(1.0, 1000.0) as u16
+        } else {
+            400
+        };
+        let width = if width.is_finite() && width > 0.0 {
+            width
+        } else {
+            100.0
+        };
+        let slant = if slant.is_finite() {
+            slant.clamp(-90.0, 90.0)
+        } else {
+            14.0
+        };
+        Ok(FontQuery {
+            family,
+            weight,
+            width,
+            style,
+            slant,
+            variations,
+        })
+    }
+
+    fn read_string(&mut self, len: usize) -> Result<String, String> {
         if self.remaining() < len {
-            return Err(JsValue::from_str("Unexpected end of buffer"));
+            return Err("Unexpected end of buffer".to_string());
         }
         let bytes = &self.data[self.offset..self.offset + len];
         self.offset += len;
         String::from_utf8(bytes.to_vec())
-            .map_err(|_| JsValue::from_str("Invalid UTF-8 in path data"))
+            .map_err(|_| "Invalid UTF-8 in string data".to_string())
     }
 }
 
@@ -762,6 +1966,14 @@ enum OpCode {
     Rect = 2,
     Path = 3,
     Text = 4,
+    PushLayer = 5,
+    PopLayer = 6,
+    PushClip = 7,
+    PushMask = 8,
+    Image = 9,
+    RichText = 10,
+    /// A `Text` or `RichText` opcode set along a path.
+    TextPath = 11,
     EndFrame = 255,
 }
 
@@ -772,109 +1984,642 @@ impl OpCode {
             2 => Some(OpCode::Rect),
             3 => Some(OpCode::Path),
             4 => Some(OpCode::Text),
+            5 => Some(OpCode::PushLayer),
+            6 => Some(OpCode::PopLayer),
+            7 => Some(OpCode::PushClip),
+            8 => Some(OpCode::PushMask),
+            9 => Some(OpCode::Image),
+            10 => Some(OpCode::RichText),
+            11 => Some(OpCode::TextPath),
             255 => Some(OpCode::EndFrame),
             _ => None,
         }
     }
 }
 
-fn measure_text_width(
-    text: &str,
-    charmap: &Charmap<'_>,
-    glyph_metrics: &GlyphMetrics<'_>,
-    fallback_width: f32,
-) -> f32 {
-    let mut width = 0.0;
-    for ch in text.chars() {
-        if ch == '\t' {
-            width += fallback_width * 4.0;
-            continue;
+fn build_binary_path(verbs: &[u8], points: &[u8]) -> Result<BezPath, String> {
+    let mut points = points.chunks_exact(8).map(|chunk| {
+        let x = f32::from_le_bytes(chunk[..4].try_into().unwrap());
+        let y = f32::from_le_bytes(chunk[4..].try_into().unwrap());
+        Point::new(x as f64, y as f64)
+    });
+    let mut next_point = || {
+        points
+            .next()
+            .ok_or_else(|| "Path verbs exceed point data".to_string())
+    };
+
+    let mut path = BezPath::new();
+    for &verb in verbs {
+        if verb != 0 && path.elements().is_empty() {
+            return Err("Path data must start with a move".to_string());
         }
-        let glyph_id = charmap.map(ch).unwrap_or(GlyphId::NOTDEF);
-        width += glyph_metrics.advance_width(glyph_id).unwrap_or(fallback_width);
+        match verb {
+            0 => path.move_to(next_point()?),
+            1 => path.line_to(next_point()?),
+            2 => path.quad_to(next_point()?, next_point()?),
+            3 => path.curve_to(next_point()?, next_point()?, next_point()?),
+            4 => path.close_path(),
+            _ => return Err("Unknown path verb".to_string()),
+        }
+    }
+    if points.next().is_some() {
+        return Err("Unused points in path data".to_string());
     }
-    width
+    Ok(path)
+}
+
+fn mix_from_u8(value: u8) -> Option<Mix> {
+    let mix = match value {
+        0 => Mix::Normal,
+        1 => Mix::Multiply,
+        2 => Mix::Screen,
+        3 => Mix::Overlay,
+        4 => Mix::Darken,
+        5 => Mix::Lighten,
+        6 => Mix::ColorDodge,
+        7 => Mix::ColorBurn,
+        8 => Mix::HardLight,
+        9 => Mix::SoftLight,
+        10 => Mix::Difference,
+        11 => Mix::Exclusion,
+        12 => Mix::Hue,
+        13 => Mix::Saturation,
+        14 => Mix::Color,
+        15 => Mix::Luminosity,
+        _ => return None,
+    };
+    Some(mix)
 }
 
-fn wrap_text_lines(
-    text: &str,
-    max_width: f32,
-    charmap: &Charmap<'_>,
-    glyph_metrics: &GlyphMetrics<'_>,
-    fallback_width: f32,
-) -> Vec<LineLayout> {
-    let mut lines = Vec::new();
-    let wrap = max_width.is_finite() && max_width > 0.0;
-    let space_width = measure_text_width(" ", charmap, glyph_metrics, fallback_width);
+fn compose_from_u8(value: u8) -> Option<Compose> {
+    let compose = match value {
+        0 => Compose::Clear,
+        1 => Compose::Copy,
+        2 => Compose::Dest,
+        3 => Compose::SrcOver,
+        4 => Compose::DestOver,
+        5 => Compose::SrcIn,
+        6 => Compose::DestIn,
+        7 => Compose::SrcOut,
+        8 => Compose::DestOut,
+        9 => Compose::SrcAtop,
+        10 => Compose::DestAtop,
+        11 => Compose::Xor,
+        12 => Compose::Plus,
+        13 => Compose::PlusLighter,
+        _ => return None,
+    };
+    Some(compose)
+}
 
-    for raw_line in text.split('\n') {
-        if !wrap {
-            let width = measure_text_width(raw_line, charmap, glyph_metrics, fallback_width);
-            lines.push(LineLayout {
-                text: raw_line.to_string(),
-                width,
-            });
-            continue;
+fn normalize_opacity(opacity: f32) -> f32 {
+    if opacity.is_finite() {
+        opacity.clamp(0.0, 1.0)
+    } else {
+        1.0
+    }
+}
+
+fn normalize_font_size(font_size: f32) -> f32 {
+    if font_size.is_finite() && font_size > 0.0 {
+        font_size
+    } else {
+        16.0
+    }
+}
+
+fn affine_from_mat3(transform: [f32; 6]) -> Affine {
+    Affine::new(transform.map(f64::from))
+}
+
+#[wasm_bindgen(start)]
+pub fn wasm_start() {
+    console_error_panic_hook::set_once();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vello::kurbo::Point;
+    use vello::peniko::GradientKind;
+    use vello_encoding::Style;
+
+    const IDENTITY: [f32; 6] = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+    const TRANSFORM: [f32; 6] = [2.0, 0.0, 0.0, 2.0, 10.0, 20.0];
+    /// Out of order, with an offset past the end of the gradient.
+    const STOPS: &[(f32, [f32; 4])] = &[
+        (1.5, [0.0, 0.0, 1.0, 1.0]),
+        (0.0, [1.0, 0.0, 0.0, 1.0]),
+        (0.5, [0.0, 1.0, 0.0, 0.5]),
+    ];
+
+    fn push_f32s(bytes: &mut Vec<u8>, values: &[f32]) {
+        for value in values {
+            bytes.extend_from_slice(&value.to_le_bytes());
         }
+    }
 
-        let words: Vec<&str> = raw_line.split_whitespace().collect();
-        if words.is_empty() {
-            lines.push(LineLayout {
-                text: String::new(),
-                width: 0.0,
-            });
-            continue;
+    /// Appends the extend mode, transform and stops shared by every gradient kind.
+    fn push_gradient(bytes: &mut Vec<u8>, extend: u8) {
+        bytes.push(extend);
+        push_f32s(bytes, &TRANSFORM);
+        bytes.extend_from_slice(&(STOPS.len() as u32).to_le_bytes());
+        for (offset, color) in STOPS {
+            push_f32s(bytes, &[*offset]);
+            push_f32s(bytes, color);
         }
+    }
 
-        let mut current = String::new();
-        let mut current_width = 0.0;
+    fn decode_gradient(bytes: &[u8]) -> Gradient {
+        let paint = Decoder::new(bytes).read_paint().unwrap();
+        assert_eq!(paint.transform, Some(affine_from_mat3(TRANSFORM)));
+        let Brush::Gradient(gradient) = paint.brush else {
+            panic!("expected a gradient brush");
+        };
+        let offsets: Vec<f32> = gradient.stops.iter().map(|stop| stop.offset).collect();
+        assert_eq!(offsets, [0.0, 0.5, 1.0]);
+        assert_eq!(gradient.stops[1].color.components, [0.0, 1.0, 0.0, 0.5]);
+        gradient
+    }
 
-        for word in words {
-            let word_width = measure_text_width(word, charmap, glyph_metrics, fallback_width);
-            if current.is_empty() {
-                current.push_str(word);
-                current_width = word_width;
-                continue;
-            }
+    #[test]
+    fn decodes_linear_gradient() {
+        let mut bytes = vec![1];
+        push_f32s(&mut bytes, &[0.0, 5.0, 100.0, 50.0]);
+        push_gradient(&mut bytes, 1);
+        let gradient = decode_gradient(&bytes);
+        let GradientKind::Linear(line) = gradient.kind else {
+            panic!("expected a linear gradient");
+        };
+        assert_eq!(line.start, Point::new(0.0, 5.0));
+        assert_eq!(line.end, Point::new(100.0, 50.0));
+        assert_eq!(gradient.extend, Extend::Repeat);
+    }
 
-            let next_width = current_width + space_width + word_width;
-            if next_width <= max_width {
-                current.push(' ');
-                current.push_str(word);
-                current_width = next_width;
-            } else {
-                lines.push(LineLayout {
-                    text: current,
-                    width: current_width,
-                });
-                current = word.to_string();
-                current_width = word_width;
-            }
+    #[test]
+    fn decodes_radial_gradient() {
+        let mut bytes = vec![2];
+        push_f32s(&mut bytes, &[10.0, 10.0, 0.0, 50.0, 50.0, 40.0]);
+        push_gradient(&mut bytes, 2);
+        let gradient = decode_gradient(&bytes);
+        let GradientKind::Radial(radial) = gradient.kind else {
+            panic!("expected a radial gradient");
+        };
+        assert_eq!(radial.start_center, Point::new(10.0, 10.0));
+        assert_eq!(radial.start_radius, 0.0);
+        assert_eq!(radial.end_center, Point::new(50.0, 50.0));
+        assert_eq!(radial.end_radius, 40.0);
+        assert_eq!(gradient.extend, Extend::Reflect);
+    }
+
+    #[test]
+    fn decodes_sweep_gradient() {
+        let mut bytes = vec![3];
+        push_f32s(&mut bytes, &[50.0, 25.0, 0.5, 3.0]);
+        push_gradient(&mut bytes, 0);
+        let gradient = decode_gradient(&bytes);
+        let GradientKind::Sweep(sweep) = gradient.kind else {
+            panic!("expected a sweep gradient");
+        };
+        assert_eq!(sweep.center, Point::new(50.0, 25.0));
+        assert_eq!((sweep.start_angle, sweep.end_angle), (0.5, 3.0));
+        assert_eq!(gradient.extend, Extend::Pad);
+    }
+
+    #[test]
+    fn decodes_blend_modes() {
+        let blend = |bytes: &[u8]| Decoder::new(bytes).read_blend_mode();
+        let mode = blend(&[15, 2]).unwrap();
+        assert_eq!((mode.mix, mode.compose), (Mix::Luminosity, Compose::Dest));
+        let mixes: Vec<Mix> = (0..16).map(|mix| blend(&[mix, 3]).unwrap().mix).collect();
+        assert_eq!(mixes[..2], [Mix::Normal, Mix::Multiply]);
+        assert!(mixes.iter().enumerate().all(|(i, mix)| !mixes[..i].contains(mix)));
+        let composes: Vec<Compose> = (0..14).map(|compose| blend(&[0, compose]).unwrap().compose).collect();
+        assert_eq!(composes[13], Compose::PlusLighter);
+        assert!(composes.iter().enumerate().all(|(i, compose)| !composes[..i].contains(compose)));
+
+        assert_eq!(blend(&[16, 3]).unwrap_err(), "Unknown blend mode");
+        assert_eq!(blend(&[0, 14]).unwrap_err(), "Unknown compose mode");
+        assert_eq!(blend(&[255, 255]).unwrap_err(), "Unknown blend mode");
+    }
+
+    #[test]
+    fn image_fit_places_a_wide_image() {
+        // A 40x20 image in a 100x100 box at (10, 20).
+        let dest = Rect::new(10.0, 20.0, 110.0, 120.0);
+        let image = Rect::new(0.0, 0.0, 40.0, 20.0);
+        let placed = |fit: ImageFit| fit.placement(dest, image.size().into()).transform_rect_bbox(image);
+
+        assert_eq!(placed(ImageFit::Stretch), dest);
+        // Contain fits the width and centres the image vertically.
+        assert_eq!(placed(ImageFit::Contain), Rect::new(10.0, 45.0, 110.0, 95.0));
+        // Cover fills the height and overflows the width on both sides.
+        assert_eq!(placed(ImageFit::Cover), Rect::new(-40.0, 20.0, 160.0, 120.0));
+        // None keeps the natural size, centred.
+        assert_eq!(placed(ImageFit::None), Rect::new(40.0, 60.0, 80.0, 80.0));
+    }
+
+    #[test]
+    fn corner_radii_scale_down_together_when_they_overlap() {
+        let rect = Rect::new(0.0, 0.0, 100.0, 50.0);
+        let fits = [Vec2::new(10.0, 5.0); 4];
+        assert_eq!(normalize_corner_radii(rect, fits), fits);
+
+        // The right corners need 72 of the 50 available height, the tightest fit, so every
+        // radius is scaled by 50/72, keeping each corner's shape.
+        let radii = [
+            Vec2::new(60.0, 60.0),
+            Vec2::new(24.0, 12.0),
+            Vec2::new(12.0, 60.0),
+            Vec2::new(0.0, 0.0),
+        ];
+        let normalized = normalize_corner_radii(rect, radii);
+        for (normalized, radius) in normalized.iter().zip(radii) {
+            assert!((*normalized - radius * (50.0 / 72.0)).hypot() < 1e-9);
         }
+    }
 
-        lines.push(LineLayout {
-            text: current,
-            width: current_width,
-        });
+    #[test]
+    fn invalid_corner_radii_are_dropped() {
+        let rect = Rect::new(0.0, 0.0, 100.0, 50.0);
+        let radii = [
+            Vec2::new(-5.0, 5.0),
+            Vec2::new(f64::NAN, 5.0),
+            Vec2::new(5.0, 0.0),
+            Vec2::new(5.0, 5.0),
+        ];
+        let expected = [Vec2::ZERO, Vec2::ZERO, Vec2::ZERO, Vec2::new(5.0, 5.0)];
+        assert_eq!(normalize_corner_radii(rect, radii), expected);
     }
 
-    lines
-}
+    #[test]
+    fn rect_shapes_use_elliptical_paths_only_when_needed() {
+        // Flipped rects are normalized before their radii are fitted.
+        let rect = Rect::new(100.0, 50.0, 0.0, 0.0);
+        let RectShape::Rounded(rounded) = rect_shape(rect, [Vec2::new(40.0, 40.0); 4]) else {
+            panic!("expected a rounded rect");
+        };
+        assert_eq!(rounded.rect(), rect.abs());
+        assert_eq!(rounded.radii(), RoundedRectRadii::from_single_radius(25.0));
 
-fn align_offset(align: TextAlign, max_width: f32, line_width: f32) -> f32 {
-    let width = if max_width.is_finite() && max_width > 0.0 {
-        max_width
-    } else {
-        line_width
-    };
-    match align {
-        TextAlign::Start => 0.0,
-        TextAlign::Center => (width - line_width) * 0.5,
-        TextAlign::End => width - line_width,
+        let RectShape::Elliptical(path) = rect_shape(rect, [Vec2::new(20.0, 10.0); 4]) else {
+            panic!("expected an elliptical path");
+        };
+        assert_eq!(path.bounding_box().round(), rect.abs());
     }
-}
 
-#[wasm_bindgen(start)]
-pub fn wasm_start() {
-    console_error_panic_hook::set_once();
+    /// Writes opcodes in the layout produced by the package's encoder.
+    #[derive(Default)]
+    struct Ops(Vec<u8>);
+
+    impl Ops {
+        fn u8(&mut self, value: u8) -> &mut Self {
+            self.0.push(value);
+            self
+        }
+
+        fn u32(&mut self, value: u32) -> &mut Self {
+            self.0.extend_from_slice(&value.to_le_bytes());
+            self
+        }
+
+        fn f32s(&mut self, values: &[f32]) -> &mut Self {
+            push_f32s(&mut self.0, values);
+            self
+        }
+
+        fn svg(&mut self, d: &str) -> &mut Self {
+            self.u8(0);
+            self.0.extend_from_slice(&(d.len() as u32).to_le_bytes());
+            self.0.extend_from_slice(d.as_bytes());
+            self
+        }
+
+        fn rect(&mut self) -> &mut Self {
+            self.u8(OpCode::Rect as u8).f32s(&[1.0]).f32s(&IDENTITY);
+            self.f32s(&[0.0, 0.0, 10.0, 10.0]).f32s(&[0.0; 8]);
+            // A solid black fill and no stroke.
+            self.u8(1).u8(0).f32s(&[0.0, 0.0, 0.0, 1.0]).u8(0)
+        }
+
+        fn path(&mut self, d: &str) -> &mut Self {
+            self.u8(OpCode::Path as u8).f32s(&[1.0]).f32s(&IDENTITY).u8(0);
+            self.u8(1).u8(0).f32s(&[0.0, 0.0, 0.0, 1.0]).u8(0);
+            self.svg(d)
+        }
+
+        fn push_clip_path(&mut self, d: &str) -> &mut Self {
+            self.u8(OpCode::PushClip as u8).f32s(&IDENTITY);
+            self.u8(3).u8(0).svg(d)
+        }
+
+        fn push_clip_rect(&mut self, rect: [f32; 4]) -> &mut Self {
+            self.u8(OpCode::PushClip as u8).f32s(&IDENTITY);
+            self.u8(1).f32s(&rect)
+        }
+
+        /// Pushes an unclipped layer at full opacity with the default blend mode.
+        fn push_layer(&mut self) -> &mut Self {
+            self.u8(OpCode::PushLayer as u8).f32s(&[1.0]).u8(0).u8(3);
+            self.f32s(&IDENTITY).u8(0)
+        }
+
+        /// Pushes a mask of `mode` clipped to `rect`.
+        fn push_mask(&mut self, mode: u8, alpha: f32, rect: [f32; 4]) -> &mut Self {
+            self.u8(OpCode::PushMask as u8).u8(mode).f32s(&[alpha]);
+            self.f32s(&IDENTITY).u8(1).f32s(&rect)
+        }
+
+        fn pop_layer(&mut self) -> &mut Self {
+            self.u8(OpCode::PopLayer as u8)
+        }
+    }
+
+    fn encode(ops: &Ops) -> (Scene, Vec<String>) {
+        let mut encoder = SceneEncoder::new(100, 100);
+        let warnings = encoder.encode(&ops.0).expect("frame encodes");
+        (encoder.scene, warnings)
+    }
+
+    /// Encodes a frame that fails, returning the error and the scene it left behind.
+    fn encode_error(ops: &Ops) -> (Scene, String) {
+        let mut encoder = SceneEncoder::new(100, 100);
+        let error = encoder.encode(&ops.0).expect_err("frame fails to encode");
+        (encoder.scene, error)
+    }
+
+    fn assert_same_drawing(actual: &Scene, expected: &Scene) {
+        let (actual, expected) = (actual.encoding(), expected.encoding());
+        assert!(actual.path_tags == expected.path_tags);
+        assert_eq!(actual.path_data, expected.path_data);
+        assert!(actual.draw_tags == expected.draw_tags);
+        assert_eq!(actual.draw_data, expected.draw_data);
+    }
+
+    #[test]
+    fn even_odd_layers_encode_like_scene_layers() {
+        // A square with a square hole, which only the even-odd rule leaves open.
+        let path = BezPath::from_svg("M 0 0 L 10 0 L 10 10 L 0 10 Z M 2 2 L 8 2 L 8 8 L 2 8 Z").unwrap();
+        let transform = affine_from_mat3(TRANSFORM);
+        let blend = BlendMode::new(Mix::Multiply, Compose::SrcOver);
+
+        let mut even_odd = Scene::new();
+        push_even_odd_layer(&mut even_odd, LayerStyle::Clip, transform, &path);
+        push_even_odd_layer(&mut even_odd, LayerStyle::Blend(blend, 0.5), transform, &path);
+        let mut non_zero = Scene::new();
+        non_zero.push_clip_layer(transform, &path);
+        non_zero.push_layer(blend, 0.5, transform, &path);
+
+        assert_same_drawing(&even_odd, &non_zero);
+        assert_eq!(even_odd.encoding().transforms, non_zero.encoding().transforms);
+        assert_eq!(even_odd.encoding().styles, [Style::from_fill(Fill::EvenOdd)]);
+        assert_eq!(non_zero.encoding().styles, [Style::from_fill(Fill::NonZero)]);
+    }
+
+    #[test]
+    fn strokes_decode_joins_caps_and_dashes() {
+        let d = "M 0 0 L 50 0 L 50 50";
+        let stroked_path = |frame: &mut Ops, join: u8, cap: u8, miter_limit: f32, dashes: &[f32]| {
+            frame.u8(OpCode::Path as u8).f32s(&[1.0]).f32s(&IDENTITY).u8(0).u8(0);
+            frame.u8(1).f32s(&[2.0]).u8(join).u8(cap).f32s(&[miter_limit]);
+            frame.u32(dashes.len() as u32).f32s(dashes).f32s(&[3.0]);
+            frame.u8(0).f32s(&[0.0, 0.0, 0.0, 1.0]).svg(d);
+        };
+        let mut frame = Ops::default();
+        stroked_path(&mut frame, 2, 1, 8.0, &[4.0, 2.0, 1.0]);
+        // Negative dashes and miter limits below one are ignored.
+        stroked_path(&mut frame, 1, 2, 0.5, &[4.0, -2.0]);
+
+        let path = BezPath::from_svg(d).unwrap();
+        let black = Color::new([0.0, 0.0, 0.0, 1.0]);
+        let mut expected = Scene::new();
+        // Odd dash patterns repeat to become even.
+        let dashed = Stroke::new(2.0)
+            .with_join(Join::Round)
+            .with_caps(Cap::Square)
+            .with_miter_limit(8.0)
+            .with_dashes(3.0, [4.0, 2.0, 1.0, 4.0, 2.0, 1.0]);
+        expected.stroke(&dashed, Affine::IDENTITY, black, None, &path);
+        let solid = Stroke::new(2.0).with_join(Join::Bevel).with_caps(Cap::Round);
+        expected.stroke(&solid, Affine::IDENTITY, black, None, &path);
+
+        let (scene, _) = encode(&frame);
+        assert_same_drawing(&scene, &expected);
+        assert_eq!(scene.encoding().styles, expected.encoding().styles);
+    }
+
+    #[test]
+    fn invalid_path_skips_only_its_node() {
+        let mut frame = Ops::default();
+        frame.rect().path("M 0 0 L 10 nope").path("M 0 0 L 10 10 Z");
+        let mut expected = Ops::default();
+        expected.rect().path("M 0 0 L 10 10 Z");
+
+        let (scene, warnings) = encode(&frame);
+        assert_same_drawing(&scene, &encode(&expected).0);
+        assert_eq!(scene.encoding().n_paths, 2);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("Invalid SVG path data"));
+    }
+
+    #[test]
+    fn invalid_clip_path_skips_its_content() {
+        let mut frame = Ops::default();
+        frame.push_clip_path("L 10").rect().pop_layer().rect();
+        let mut expected = Ops::default();
+        expected.push_clip_rect([0.0; 4]).rect().pop_layer().rect();
+
+        let (scene, warnings) = encode(&frame);
+        assert_same_drawing(&scene, &encode(&expected).0);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn unbalanced_layers_fail_the_frame() {
+        let mut frame = Ops::default();
+        frame.push_layer().rect().push_layer().rect().pop_layer();
+        let (scene, error) = encode_error(&frame);
+        assert_eq!(error, "Unbalanced layers: 1 pushed layer(s) without a matching PopLayer");
+        assert!(scene.encoding().is_empty());
+    }
+
+    #[test]
+    fn stray_pop_layer_fails_the_frame() {
+        let mut frame = Ops::default();
+        frame.push_layer().rect().pop_layer().pop_layer().rect();
+        let (scene, error) = encode_error(&frame);
+        assert_eq!(error, "PopLayer without a matching push");
+        assert!(scene.encoding().is_empty());
+    }
+
+    #[test]
+    fn masks_need_an_isolating_layer_directly_around_them() {
+        let mut decoder = Decoder::new(&[]);
+        assert!(decoder.push_mask().is_err());
+
+        decoder.push_layer(LayerKind::Layer);
+        assert!(decoder.push_mask().is_ok());
+        // A mask inside a mask modulates nothing isolated.
+        assert!(decoder.push_mask().is_err());
+        decoder.pop_layer().unwrap();
+
+        // A clip inside the layer does not isolate the mask.
+        decoder.push_layer(LayerKind::Clip);
+        assert!(decoder.push_mask().is_err());
+        decoder.pop_layer().unwrap();
+        assert!(decoder.push_mask().is_ok());
+    }
+
+    /// Mirrors `Ops::rect` drawn straight into `scene`.
+    fn fill_square(scene: &mut Scene) {
+        let square = RoundedRect::from_rect(Rect::new(0.0, 0.0, 10.0, 10.0), 0.0);
+        let black = Color::new([0.0, 0.0, 0.0, 1.0]);
+        scene.fill(Fill::NonZero, Affine::IDENTITY, black, None, &square);
+    }
+
+    #[test]
+    fn masks_encode_as_mask_layers() {
+        let viewport = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let mask_rect = Rect::new(0.0, 0.0, 5.0, 5.0);
+        let masked = |mode: u8| {
+            let mut frame = Ops::default();
+            frame.push_layer().rect().push_mask(mode, 0.5, [0.0, 0.0, 5.0, 5.0]);
+            frame.rect().pop_layer().pop_layer();
+            encode(&frame).0
+        };
+        let expected = |push_mask: &dyn Fn(&mut Scene)| {
+            let mut scene = Scene::new();
+            scene.push_layer(BlendMode::default(), 1.0, Affine::IDENTITY, &viewport);
+            fill_square(&mut scene);
+            push_mask(&mut scene);
+            fill_square(&mut scene);
+            scene.pop_layer();
+            scene.pop_layer();
+            scene
+        };
+
+        // Alpha masks keep the layer's content where the mask content covers it.
+        let dest_in = BlendMode::new(Mix::Normal, Compose::DestIn);
+        let alpha = expected(&|scene| scene.push_layer(dest_in, 0.5, Affine::IDENTITY, &mask_rect));
+        assert_same_drawing(&masked(0), &alpha);
+
+        let luminance =
+            expected(&|scene| scene.push_luminance_mask_layer(0.5, Affine::IDENTITY, &mask_rect));
+        assert_same_drawing(&masked(1), &luminance);
+    }
+
+    #[test]
+    fn masks_outside_a_layer_fail_the_frame() {
+        let mut frame = Ops::default();
+        frame.rect().push_mask(0, 1.0, [0.0, 0.0, 5.0, 5.0]).rect().pop_layer();
+        let (scene, error) = encode_error(&frame);
+        assert_eq!(error, "PushMask must be directly inside a PushLayer");
+        assert!(scene.encoding().is_empty());
+
+        let mut frame = Ops::default();
+        frame.push_layer().push_clip_rect([0.0, 0.0, 5.0, 5.0]);
+        frame.push_mask(1, 1.0, [0.0, 0.0, 5.0, 5.0]).pop_layer().pop_layer().pop_layer();
+        assert_eq!(encode_error(&frame).1, "PushMask must be directly inside a PushLayer");
+    }
+
+    /// A single-span `Text` op in the bundled font, laid out on one line.
+    fn text_op(text: &str) -> TextOp {
+        TextOp {
+            opacity: 1.0,
+            transform: IDENTITY,
+            ox: 0.0,
+            oy: 0.0,
+            align: TextAlign::Start,
+            options: TextLayoutOptions::test(0.0),
+            text: text.to_string(),
+            spans: vec![TextSpanOp {
+                range: 0..text.len(),
+                font: FontQuery {
+                    family: String::new(),
+                    weight: 400,
+                    width: 100.0,
+                    style: FontStyle::Normal,
+                    slant: 14.0,
+                    variations: Vec::new(),
+                },
+                font_size: 16.0,
+                line_height: 0.0,
+                letter_spacing: 0.0,
+                paint: TextPaint {
+                    fill: None,
+                    stroke: None,
+                    stroke_first: false,
+                },
+                decoration: TextDecoration::default(),
+                decoration_paint: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn text_strokes_beneath_the_fill_go_before_every_span_fill() {
+        let text = "ab cd ef";
+        let mut op = text_op(text);
+        let solid = |color: Color| Paint { brush: Brush::Solid(color), transform: None };
+        let span = |range: Range<usize>, width: f64, stroke_first: bool| TextSpanOp {
+            range,
+            paint: TextPaint {
+                fill: Some(solid(Color::BLACK)),
+                stroke: Some((Stroke::new(width), solid(Color::WHITE))),
+                stroke_first,
+            },
+            ..text_op(text).spans.remove(0)
+        };
+        op.spans = vec![span(0..3, 4.0, true), span(3..6, 3.0, true), span(6..8, 2.0, false)];
+
+        let mut encoder = SceneEncoder::new(100, 100);
+        let (fonts, layout) = encoder.layout_text(&op).unwrap();
+        let mut runs = Vec::new();
+        for placed in &layout.lines {
+            placed.line.push_glyphs(&mut runs, placed.x, placed.baseline);
+        }
+        let bounds = layout.bounds();
+        encoder.draw_glyph_runs(&op, &fonts, runs, Affine::IDENTITY, Affine::IDENTITY, bounds);
+
+        // Halos of both stroke-first spans, then every fill, then the stroke drawn over its fill.
+        let stroke_widths: Vec<Option<f64>> = encoder
+            .scene
+            .encoding()
+            .resources
+            .glyph_runs
+            .iter()
+            .map(|run| match &run.style {
+                vello::peniko::Style::Fill(_) => None,
+                vello::peniko::Style::Stroke(stroke) => Some(stroke.width),
+            })
+            .collect();
+        assert_eq!(stroke_widths, [Some(4.0), Some(3.0), None, None, None, Some(2.0)]);
+    }
+
+    #[test]
+    fn text_queries_round_trip_utf16_indices() {
+        // UTF-16 boundaries: "a" at 0, a surrogate pair at 1, "e" and a combining acute
+        // accent at 3, "b" at 5 and the end at 6.
+        let op = text_op("a\u{1F600}e\u{301}b");
+        let text = &op.text;
+        let (_, layout) = SceneEncoder::new(100, 100).layout_text(&op).unwrap();
+        let caret = |index: u32| layout.caret(text, byte_index(text, index));
+        let y = caret(0).center().y as f32;
+        for index in [0, 1, 3, 5, 6] {
+            let x = caret(index).x0 as f32;
+            assert_eq!(utf16_index(text, layout.hit_test(text, x, y)), index);
+        }
+
+        // Indices inside the surrogate pair or the accented grapheme snap to its start.
+        assert_eq!(caret(2), caret(1));
+        assert_eq!(caret(4), caret(3));
+
+        // A selection from inside the surrogate pair to inside the accented grapheme covers
+        // both graphemes whole.
+        let selection = layout.selection(text, byte_index(text, 2)..byte_index(text, 4));
+        assert_eq!(selection.len(), 1);
+        assert_eq!(selection[0].x0, caret(1).x0);
+        assert_eq!(selection[0].x1, caret(5).x0);
+    }
 }
@@ -0,0 +1,2281 @@
+use std::cmp::Reverse;
+use std::mem;
+use std::ops::Range;
+
+use harfrust::{Direction, Shaper, UnicodeBuffer};
+use skrifa::bitmap::BitmapStrikes;
+use skrifa::charmap::Charmap;
+use skrifa::color::ColorGlyphCollection;
+use skrifa::instance::{LocationRef, Size};
+use skrifa::metrics::Decoration;
+use skrifa::outline::{OutlineGlyphCollection, OutlinePen};
+use skrifa::raw::TableProvider;
+use skrifa::{FontRef, GlyphId, MetadataProvider};
+use unicode_bidi::{BidiInfo, Level};
+use unicode_linebreak::{linebreaks, BreakOpportunity};
+use unicode_segmentation::UnicodeSegmentation;
+use vello::kurbo::{flatten, Affine, BezPath, PathEl, Point, Rect};
+
+use crate::fonts::FontInstance;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum TextAlign {
+    Start,
+    Center,
+    End,
+    /// Stretches word separators so every line but the last of a paragraph fills the width.
+    Justify,
+}
+
+impl TextAlign {
+    pub(crate) fn from_u8(value: u8) -> Self {
+        match value {
+            1 => TextAlign::Center,
+            2 => TextAlign::End,
+            3 => TextAlign::Justify,
+            _ => TextAlign::Start,
+        }
+    }
+}
+
+/// A glyph produced by shaping, positioned relative to the pen.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct ShapedGlyph {
+    /// Index of the span the glyph was shaped with.
+    pub(crate) span: usize,
+    /// Index of the face in the span's font chain.
+    pub(crate) face: usize,
+    pub(crate) id: u32,
+    /// Byte offset of the cluster this glyph belongs to in the shaped text, remapped to the
+    /// laid out text once the glyph is placed on a line.
+    pub(crate) cluster: usize,
+    pub(crate) x_offset: f32,
+    pub(crate) y_offset: f32,
+    pub(crate) advance: f32,
+    pub(crate) visible: bool,
+    /// Whether the glyph is a word separator, which takes word spacing and justification.
+    pub(crate) separator: bool,
+    /// Whether the glyph is a COLR or bitmap glyph that carries its own paints.
+    pub(crate) color: bool,
+    /// Whether the glyph was shaped right to left.
+    pub(crate) rtl: bool,
+    /// Whether the glyph stands upright in vertical text rather than lying sideways.
+    pub(crate) upright: bool,
+}
+
+#[cfg(test)]
+impl ShapedGlyph {
+    /// A visible left-to-right glyph of the first face of span 0, at cluster 0.
+    pub(crate) fn test(id: u32, advance: f32) -> Self {
+        ShapedGlyph {
+            span: 0,
+            face: 0,
+            id,
+            cluster: 0,
+            x_offset: 0.0,
+            y_offset: 0.0,
+            advance,
+            visible: true,
+            separator: false,
+            color: false,
+            rtl: false,
+            upright: false,
+        }
+    }
+}
+
+/// Base direction of a paragraph; `Auto` resolves it from the first strong character.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum TextDirection {
+    Auto,
+    Ltr,
+    Rtl,
+}
+
+impl TextDirection {
+    pub(crate) fn from_u8(value: u8) -> Self {
+        match value {
+            1 => TextDirection::Ltr,
+            2 => TextDirection::Rtl,
+            _ => TextDirection::Auto,
+        }
+    }
+
+    fn default_level(self) -> Option<Level> {
+        match self {
+            TextDirection::Auto => None,
+            TextDirection::Ltr => Some(Level::ltr()),
+            TextDirection::Rtl => Some(Level::rtl()),
+        }
+    }
+}
+
+/// Direction lines run in and stack, mirroring CSS `writing-mode`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum WritingMode {
+    HorizontalTb,
+    /// Lines run top to bottom as columns that stack from right to left.
+    VerticalRl,
+}
+
+impl WritingMode {
+    pub(crate) fn from_u8(value: u8) -> Self {
+        match value {
+            1 => WritingMode::VerticalRl,
+            _ => WritingMode::HorizontalTb,
+        }
+    }
+}
+
+/// Orientation of glyphs in vertical text, mirroring CSS `text-orientation`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum TextOrientation {
+    /// Scripts that are written vertically stand upright; the rest are turned sideways.
+    Mixed,
+    Upright,
+    Sideways,
+}
+
+impl TextOrientation {
+    pub(crate) fn from_u8(value: u8) -> Self {
+        match value {
+            1 => TextOrientation::Upright,
+            2 => TextOrientation::Sideways,
+            _ => TextOrientation::Mixed,
+        }
+    }
+}
+
+/// How whitespace is treated when wrapping, mirroring CSS `white-space`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum WhiteSpace {
+    /// Collapses runs of whitespace to single spaces.
+    Normal,
+    /// Preserves whitespace while still wrapping.
+    PreWrap,
+    /// Preserves whitespace and never wraps.
+    Pre,
+}
+
+impl WhiteSpace {
+    pub(crate) fn from_u8(value: u8) -> Self {
+        match value {
+            1 => WhiteSpace::PreWrap,
+            2 => WhiteSpace::Pre,
+            _ => WhiteSpace::Normal,
+        }
+    }
+}
+
+/// Whether words wider than the line may be broken, mirroring CSS `overflow-wrap`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum OverflowWrap {
+    Normal,
+    BreakWord,
+}
+
+impl OverflowWrap {
+    pub(crate) fn from_u8(value: u8) -> Self {
+        match value {
+            1 => OverflowWrap::BreakWord,
+            _ => OverflowWrap::Normal,
+        }
+    }
+}
+
+/// How text that does not fit is shown, mirroring CSS `text-overflow`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TextOverflow {
+    /// Lines wider than the wrapping width spill out of the box.
+    Visible,
+    /// Lines are clipped to the wrapping width.
+    Clip,
+    /// Lines wider than the wrapping width, and the last visible line when later lines are
+    /// dropped, end with the given string.
+    Ellipsis(String),
+}
+
+impl TextOverflow {
+    pub(crate) fn new(mode: u8, ellipsis: String) -> Self {
+        match mode {
+            1 => TextOverflow::Clip,
+            2 if ellipsis.is_empty() => TextOverflow::Ellipsis("\u{2026}".to_string()),
+            2 => TextOverflow::Ellipsis(ellipsis),
+            _ => TextOverflow::Visible,
+        }
+    }
+}
+
+/// Pattern of a decoration line, mirroring CSS `text-decoration-style`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub(crate) enum DecorationStyle {
+    #[default]
+    Solid,
+    Dashed,
+    Wavy,
+}
+
+impl DecorationStyle {
+    pub(crate) fn from_u8(value: u8) -> Self {
+        match value {
+            1 => DecorationStyle::Dashed,
+            2 => DecorationStyle::Wavy,
+            _ => DecorationStyle::Solid,
+        }
+    }
+}
+
+/// Lines drawn along a span, mirroring CSS `text-decoration`.
+#[derive(Debug, Copy, Clone, Default)]
+pub(crate) struct TextDecoration {
+    pub(crate) underline: bool,
+    pub(crate) line_through: bool,
+    pub(crate) overline: bool,
+    pub(crate) style: DecorationStyle,
+    /// Line thickness; zero or negative uses the font's underline and strikeout metrics.
+    pub(crate) thickness: f32,
+}
+
+impl TextDecoration {
+    /// `lines` has bit 0 set for underline, bit 1 for line-through and bit 2 for overline.
+    pub(crate) fn new(lines: u8, style: u8, thickness: f32) -> Self {
+        TextDecoration {
+            underline: lines & 1 != 0,
+            line_through: lines & 2 != 0,
+            overline: lines & 4 != 0,
+            style: DecorationStyle::from_u8(style),
+            thickness,
+        }
+    }
+
+    fn thickness(&self, metric: f32) -> f32 {
+        if self.thickness.is_finite() && self.thickness > 0.0 {
+            self.thickness
+        } else {
+            metric
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum DecorationKind {
+    Underline,
+    LineThrough,
+    Overline,
+}
+
+/// A straight stretch of one decoration line of a span, relative to the text origin.
+pub(crate) struct DecorationLine {
+    pub(crate) span: usize,
+    pub(crate) kind: DecorationKind,
+    pub(crate) style: DecorationStyle,
+    pub(crate) x0: f32,
+    pub(crate) x1: f32,
+    /// Vertical center of the line.
+    pub(crate) y: f32,
+    pub(crate) thickness: f32,
+}
+
+/// Paragraph settings shared by every span of a `Text` or `RichText` opcode.
+pub(crate) struct TextLayoutOptions {
+    /// Wrapping width; zero or non-finite disables wrapping.
+    pub(crate) max_width: f32,
+    pub(crate) direction: TextDirection,
+    pub(crate) white_space: WhiteSpace,
+    pub(crate) overflow_wrap: OverflowWrap,
+    /// Extra advance after every word separator, on top of the span's letter spacing.
+    pub(crate) word_spacing: f32,
+    /// Number of lines kept; `None` keeps every line.
+    pub(crate) max_lines: Option<usize>,
+    pub(crate) overflow: TextOverflow,
+    pub(crate) writing_mode: WritingMode,
+    pub(crate) orientation: TextOrientation,
+}
+
+impl TextLayoutOptions {
+    /// Glyph orientation of vertical text; `None` for horizontal text.
+    fn vertical(&self) -> Option<TextOrientation> {
+        (self.writing_mode == WritingMode::VerticalRl).then_some(self.orientation)
+    }
+}
+
+#[cfg(test)]
+impl TextLayoutOptions {
+    /// Left-to-right text wrapping at `max_width`, with every other setting at its default.
+    pub(crate) fn test(max_width: f32) -> Self {
+        TextLayoutOptions {
+            max_width,
+            direction: TextDirection::Ltr,
+            white_space: WhiteSpace::Normal,
+            overflow_wrap: OverflowWrap::Normal,
+            word_spacing: 0.0,
+            max_lines: None,
+            overflow: TextOverflow::Visible,
+            writing_mode: WritingMode::HorizontalTb,
+            orientation: TextOrientation::Mixed,
+        }
+    }
+}
+
+/// A line of glyphs in visual order.
+pub(crate) struct LineLayout {
+    /// Logical text of the line, including any ellipsis.
+    pub(crate) text: String,
+    /// Byte range of the line in the laid out text, without hanging whitespace or the
+    /// ellipsis.
+    pub(crate) range: Range<usize>,
+    pub(crate) glyphs: Vec<ShapedGlyph>,
+    pub(crate) width: f32,
+    /// Whether the line's paragraph runs right to left, which flips start and end alignment.
+    pub(crate) rtl: bool,
+    /// Whether `Justify` may stretch the line; false for the last line of a paragraph and for
+    /// lines ending in an ellipsis.
+    pub(crate) justifiable: bool,
+    /// Ascent of the tallest span on the line.
+    pub(crate) ascent: f32,
+    /// Descent of the deepest span on the line, negative below the baseline.
+    pub(crate) descent: f32,
+    /// Advance to the next line, the largest line height of the spans on the line.
+    pub(crate) height: f32,
+}
+
+impl LineLayout {
+    fn new(text: String, range: Range<usize>, glyphs: Vec<ShapedGlyph>, rtl: bool) -> Self {
+        let width = glyphs.iter().map(|glyph| glyph.advance).sum();
+        LineLayout {
+            text,
+            range,
+            glyphs,
+            width,
+            rtl,
+            justifiable: false,
+            ascent: 0.0,
+            descent: 0.0,
+            height: 0.0,
+        }
+    }
+
+    /// Takes the line's vertical metrics from the spans of its glyphs, or from `fallback` when
+    /// the line is empty. Vertical lines are centred on their baseline, which is the central
+    /// line of the column.
+    fn measure(&mut self, spans: &[TextSpan<'_>], fallback: usize, vertical: bool) {
+        let mut used: Vec<usize> = self.glyphs.iter().map(|glyph| glyph.span).collect();
+        used.sort_unstable();
+        used.dedup();
+        if used.is_empty() {
+            used.push(fallback);
+        }
+        let used = used.iter().map(|&index| &spans[index]);
+        self.ascent = used
+            .clone()
+            .map(|span| span.chain.ascent)
+            .fold(f32::MIN, f32::max);
+        self.descent = used
+            .clone()
+            .map(|span| span.chain.descent)
+            .fold(f32::MAX, f32::min);
+        self.height = used.map(TextSpan::line_height).fold(0.0, f32::max);
+        if vertical {
+            self.ascent = self.height * 0.5;
+            self.descent = -self.height * 0.5;
+        }
+    }
+
+    /// Spreads the space left in `max_width` evenly over the line's word separators.
+    fn justify(&mut self, max_width: f32) {
+        let extra = max_width - self.width;
+        let separators = self.glyphs.iter().filter(|glyph| glyph.separator).count();
+        if !self.justifiable || extra <= 0.0 || separators == 0 {
+            return;
+        }
+        let share = extra / separators as f32;
+        for glyph in self.glyphs.iter_mut().filter(|glyph| glyph.separator) {
+            glyph.advance += share;
+        }
+        self.width = max_width;
+    }
+
+    /// Appends the glyphs of this line with its pen starting at `x`, splitting runs where the
+    /// span or the resolved face changes.
+    pub(crate) fn push_glyphs(&self, runs: &mut Vec<GlyphRun>, mut x: f32, y: f32) {
+        for shaped in &self.glyphs {
+            if shaped.visible {
+                let glyph = vello::Glyph {
+                    id: shaped.id,
+                    x: x + shaped.x_offset,
+                    y: y - shaped.y_offset,
+                };
+                match runs.last_mut() {
+                    Some(run)
+                        if run.span == shaped.span
+                            && run.face == shaped.face
+                            && run.color == shaped.color
+                            && run.upright == shaped.upright =>
+                    {
+                        run.glyphs.push(glyph)
+                    }
+                    _ => runs.push(GlyphRun {
+                        span: shaped.span,
+                        face: shaped.face,
+                        color: shaped.color,
+                        upright: shaped.upright,
+                        transform: Affine::IDENTITY,
+                        glyphs: vec![glyph],
+                    }),
+                }
+            }
+            x += shaped.advance;
+        }
+    }
+}
+
+/// Glyphs that share a span, a face in its font chain, whether they are color glyphs and
+/// their orientation, drawn with a single `draw_glyphs` call.
+pub(crate) struct GlyphRun {
+    pub(crate) span: usize,
+    pub(crate) face: usize,
+    pub(crate) color: bool,
+    /// Whether the glyphs stand upright in vertical text, turned against the line frame.
+    pub(crate) upright: bool,
+    /// Placement of the glyphs in the line frame, which turns glyphs set along a path.
+    pub(crate) transform: Affine,
+    pub(crate) glyphs: Vec<vello::Glyph>,
+}
+
+struct Face<'a> {
+    font: FontRef<'a>,
+    location: LocationRef<'a>,
+    charmap: Charmap<'a>,
+    shaper: Shaper<'a>,
+    /// Whether the face has COLR/CPAL or bitmap glyphs, such as an emoji font.
+    has_color: bool,
+    color_glyphs: ColorGlyphCollection<'a>,
+    bitmaps: BitmapStrikes<'a>,
+    scale: f32,
+    /// Width of the synthetic bold stroke, added to the advance of every glyph.
+    embolden: f32,
+    glyph_transform: Affine,
+}
+
+/// A primary font followed by its fallbacks, consulted in order for each grapheme.
+pub(crate) struct FontChain<'a> {
+    faces: Vec<Face<'a>>,
+    pub(crate) font_size: f32,
+    pub(crate) ascent: f32,
+    pub(crate) descent: f32,
+    pub(crate) leading: f32,
+    pub(crate) fallback_width: f32,
+    /// Underline position and thickness of the primary font, from its `post` table.
+    underline: Decoration,
+    /// Strikeout position and thickness of the primary font, from its `OS/2` table.
+    strikeout: Decoration,
+}
+
+impl<'a> FontChain<'a> {
+    pub(crate) fn new(fonts: &'a [FontInstance], font_size: f32) -> Result<Self, String> {
+        let size = Size::new(font_size);
+        let mut faces = Vec::with_capacity(fonts.len());
+        let mut primary_metrics = None;
+        for instance in fonts {
+            let font = &instance.font;
+            let font_ref = FontRef::from_index(font.data.data.as_ref(), font.data.index)
+                .map_err(|_| "Invalid font data")?;
+            let location = LocationRef::new(instance.coords());
+            if primary_metrics.is_none() {
+                primary_metrics = Some(font_ref.metrics(size, location));
+            }
+            let shaper = font
+                .shaper_data
+                .shaper(&font_ref)
+                .instance(Some(&instance.shaper_instance))
+                .point_size(Some(font_size))
+                .build();
+            let scale = font_size / shaper.units_per_em().max(1) as f32;
+            let bitmaps = font_ref.bitmap_strikes();
+            faces.push(Face {
+                font: font_ref.clone(),
+                location,
+                charmap: font_ref.charmap(),
+                shaper,
+                has_color: font_ref.colr().is_ok() && font_ref.cpal().is_ok() || !bitmaps.is_empty(),
+                color_glyphs: font_ref.color_glyphs(),
+                bitmaps,
+                scale,
+                embolden: instance.embolden * font_size,
+                glyph_transform: instance.glyph_transform().unwrap_or_default(),
+            });
+        }
+        let metrics = primary_metrics.ok_or("No fonts available")?;
+
+        let ascent = if metrics.ascent.is_finite() {
+            metrics.ascent
+        } else {
+            font_size * 0.8
+        };
+        let descent = if metrics.descent.is_finite() {
+            metrics.descent
+        } else {
+            -font_size * 0.2
+        };
+        let leading = if metrics.leading.is_finite() {
+            metrics.leading
+        } else {
+            0.0
+        };
+        let fallback_width = metrics
+            .average_width
+            .filter(|width| width.is_finite() && *width > 0.0)
+            .unwrap_or(font_size * 0.5);
+        let valid = |decoration: &Decoration| {
+            decoration.offset.is_finite() && decoration.thickness.is_finite() && decoration.thickness > 0.0
+        };
+        let underline = metrics.underline.filter(valid).unwrap_or(Decoration {
+            offset: -font_size * 0.1,
+            thickness: font_size / 14.0,
+        });
+        let strikeout = metrics.strikeout.filter(valid).unwrap_or(Decoration {
+            offset: font_size * 0.3,
+            thickness: font_size / 14.0,
+        });
+
+        Ok(FontChain {
+            faces,
+            font_size,
+            ascent,
+            descent,
+            leading,
+            fallback_width,
+            underline,
+            strikeout,
+        })
+    }
+
+    /// Picks the first face that covers every character of `grapheme`, preferring a color
+    /// face for emoji and a monochrome face for text presentation sequences.
+    fn face_for(&self, grapheme: &str) -> Option<usize> {
+        let covers = |face: &Face<'_>| {
+            grapheme.chars().all(|ch| {
+                is_default_ignorable(ch)
+                    || face
+                        .charmap
+                        .map(ch)
+                        .is_some_and(|glyph_id| glyph_id != GlyphId::NOTDEF)
+            })
+        };
+        prefers_color(grapheme)
+            .and_then(|color| {
+                self.faces
+                    .iter()
+                    .position(|face| face.has_color == color && covers(face))
+            })
+            .or_else(|| self.faces.iter().position(covers))
+    }
+
+    /// Whether the renderer draws `glyph_id` of `face` from its COLR layers or a bitmap
+    /// strike rather than its outline.
+    fn is_color_glyph(&self, face: &Face<'_>, glyph_id: GlyphId) -> bool {
+        face.has_color
+            && (face.color_glyphs.get(glyph_id).is_some()
+                || face
+                    .bitmaps
+                    .glyph_for_size(Size::new(self.font_size), glyph_id)
+                    .is_some())
+    }
+
+    /// Horizontal extents where the outlines of `glyphs`, drawn with their pen starting at `x`,
+    /// enter the band `y0..y1` below `baseline`.
+    fn ink_extents(
+        &self,
+        glyphs: &[ShapedGlyph],
+        mut x: f32,
+        baseline: f32,
+        y0: f32,
+        y1: f32,
+    ) -> Vec<(f32, f32)> {
+        let mut outlines: Vec<Option<OutlineGlyphCollection<'_>>> = self.faces.iter().map(|_| None).collect();
+        let size = Size::new(self.font_size);
+        let mut extents = Vec::new();
+        for glyph in glyphs {
+            let origin = (x + glyph.x_offset, baseline - glyph.y_offset);
+            x += glyph.advance;
+            // Upright glyphs in vertical text are turned against the line, so their outlines
+            // do not measure extents along it.
+            if !glyph.visible || glyph.upright {
+                continue;
+            }
+            let face = &self.faces[glyph.face];
+            let collection = outlines[glyph.face].get_or_insert_with(|| face.font.outline_glyphs());
+            let Some(outline) = collection.get(GlyphId::new(glyph.id)) else {
+                continue;
+            };
+            let mut pen = OutlinePath(BezPath::new());
+            if outline.draw((size, face.location), &mut pen).is_err() {
+                continue;
+            }
+            // Outlines are y-up; flip them into the layout's y-down space.
+            let flip = Affine::new([1.0, 0.0, 0.0, -1.0, origin.0 as f64, origin.1 as f64]);
+            let path = flip * face.glyph_transform * pen.0;
+            // Synthetic bold strokes reach half their width beyond the outline.
+            let reach = face.embolden * 0.5;
+            if let Some((left, right)) = band_extent(&path, (y0 - reach) as f64, (y1 + reach) as f64) {
+                extents.push((left as f32 - reach, right as f32 + reach));
+            }
+        }
+        extents
+    }
+
+    /// Splits `text[range]` into runs of graphemes that resolve to the same face, bidi level
+    /// and, in vertical text, orientation. Whitespace stays with the surrounding run so
+    /// fallback does not break kerning across spaces.
+    fn itemize(
+        &self,
+        text: &str,
+        range: Range<usize>,
+        levels: &[Level],
+        vertical: Option<TextOrientation>,
+    ) -> Vec<(usize, Level, bool, Range<usize>)> {
+        let mut runs: Vec<(usize, Level, bool, Range<usize>)> = Vec::new();
+        for (offset, grapheme) in text[range.clone()].grapheme_indices(true) {
+            let start = range.start + offset;
+            let end = start + grapheme.len();
+            let level = levels[start];
+            let (face, upright) = if grapheme.chars().all(char::is_whitespace) {
+                runs.last()
+                    .map_or((0, false), |(face, _, upright, _)| (*face, *upright))
+            } else {
+                let upright = match vertical {
+                    None | Some(TextOrientation::Sideways) => false,
+                    Some(TextOrientation::Upright) => true,
+                    Some(TextOrientation::Mixed) => grapheme.chars().next().is_some_and(is_upright),
+                };
+                (self.face_for(grapheme).unwrap_or(0), upright)
+            };
+            match runs.last_mut() {
+                Some((run_face, run_level, run_upright, range))
+                    if *run_face == face && *run_level == level && *run_upright == upright =>
+                {
+                    range.end = end
+                }
+                _ => runs.push((face, level, upright, start..end)),
+            }
+        }
+        runs
+    }
+
+    /// Shapes `text[range]` as span `span` with the per-byte bidi `levels` of `text`. Each
+    /// run's glyphs are in visual order, with clusters as byte offsets into `text`.
+    ///
+    /// Vertical text is shaped in the frame of its line, which is turned a quarter turn
+    /// clockwise onto the page. Sideways glyphs are centred on the line; upright glyphs are
+    /// shaped top to bottom with the font's vertical metrics and their positions turned into
+    /// the frame.
+    fn shape(
+        &self,
+        text: &str,
+        range: Range<usize>,
+        levels: &[Level],
+        span: usize,
+        vertical: Option<TextOrientation>,
+    ) -> Vec<ShapedGlyph> {
+        let sideways_shift = if vertical.is_some() {
+            (self.ascent + self.descent) * 0.5
+        } else {
+            0.0
+        };
+        let mut glyphs = Vec::new();
+        for (face_index, level, upright, range) in self.itemize(text, range, levels, vertical) {
+            let face = &self.faces[face_index];
+            let mut buffer = UnicodeBuffer::new();
+            buffer.push_str(&text[range.clone()]);
+            buffer.set_direction(if upright {
+                Direction::TopToBottom
+            } else if level.is_rtl() {
+                Direction::RightToLeft
+            } else {
+                Direction::LeftToRight
+            });
+            buffer.guess_segment_properties();
+            let output = face.shaper.shape(buffer, &[]);
+            for (info, position) in output.glyph_infos().iter().zip(output.glyph_positions()) {
+                let cluster = range.start + info.cluster as usize;
+                let tab = text[cluster..].starts_with('\t');
+                let color = self.is_color_glyph(face, GlyphId::new(info.glyph_id));
+                // Color glyphs are drawn as they are, without synthetic bold.
+                let embolden = if color { 0.0 } else { face.embolden };
+                // Vertical advances and offsets are y-up along the column.
+                let (advance, x_offset, y_offset) = if upright {
+                    let offset = (-position.y_offset as f32, position.x_offset as f32);
+                    (-position.y_advance, offset.0 * face.scale, offset.1 * face.scale)
+                } else {
+                    let offset = (position.x_offset as f32, position.y_offset as f32);
+                    (
+                        position.x_advance,
+                        offset.0 * face.scale,
+                        offset.1 * face.scale - sideways_shift,
+                    )
+                };
+                glyphs.push(ShapedGlyph {
+                    span,
+                    face: face_index,
+                    id: info.glyph_id,
+                    cluster,
+                    x_offset,
+                    y_offset,
+                    advance: if tab {
+                        self.fallback_width * 4.0
+                    } else if advance != 0 {
+                        advance as f32 * face.scale + embolden
+                    } else {
+                        0.0
+                    },
+                    visible: !tab,
+                    separator: text[cluster..].starts_with(is_word_separator),
+                    color,
+                    rtl: level.is_rtl(),
+                    upright,
+                });
+            }
+        }
+        glyphs
+    }
+}
+
+/// A styled range of the laid out text with its own fonts, size and spacing.
+pub(crate) struct TextSpan<'a> {
+    pub(crate) chain: FontChain<'a>,
+    /// Byte range of the span in the laid out text.
+    pub(crate) range: Range<usize>,
+    /// Extra advance after every grapheme of the span.
+    pub(crate) letter_spacing: f32,
+    /// Line height the span needs; zero or negative uses its font's ascent, descent and
+    /// line gap.
+    pub(crate) line_height: f32,
+    pub(crate) decoration: TextDecoration,
+}
+
+impl TextSpan<'_> {
+    fn line_height(&self) -> f32 {
+        if self.line_height.is_finite() && self.line_height > 0.0 {
+            return self.line_height;
+        }
+        let chain = &self.chain;
+        let base = chain.ascent - chain.descent + chain.leading;
+        if base.is_finite() && base > 0.0 {
+            base
+        } else {
+            chain.font_size * 1.2
+        }
+    }
+}
+
+/// A paragraph of the laid out text, after whitespace collapsing.
+struct Paragraph {
+    text: String,
+    /// Span of every byte of `text`, followed by the span at the paragraph end.
+    spans: Vec<usize>,
+    /// Offset in the laid out text of every byte of `text`, followed by the paragraph end.
+    sources: Vec<usize>,
+}
+
+impl Paragraph {
+    fn push(&mut self, ch: char, span: usize, source: usize) {
+        self.text.push(ch);
+        self.spans.extend(std::iter::repeat_n(span, ch.len_utf8()));
+        self.sources.extend(source..source + ch.len_utf8());
+    }
+
+    /// Byte ranges of `text` that belong to a single span, in logical order.
+    fn span_runs(&self) -> Vec<(usize, Range<usize>)> {
+        let mut runs: Vec<(usize, Range<usize>)> = Vec::new();
+        for (offset, &span) in self.spans[..self.text.len()].iter().enumerate() {
+            match runs.last_mut() {
+                Some((run_span, range)) if *run_span == span => range.end = offset + 1,
+                _ => runs.push((span, offset..offset + 1)),
+            }
+        }
+        runs
+    }
+}
+
+/// Splits `text` into paragraphs at newlines, collapsing runs of whitespace to single spaces
+/// when `collapse` is set, and records the span every remaining byte came from.
+fn split_paragraphs(text: &str, spans: &[TextSpan<'_>], collapse: bool) -> Vec<Paragraph> {
+    let span_at = |offset: usize| {
+        spans
+            .iter()
+            .rposition(|span| span.range.start <= offset)
+            .unwrap_or(0)
+    };
+    let mut paragraphs = Vec::new();
+    let mut start = 0;
+    for raw in text.split('\n') {
+        let mut paragraph = Paragraph {
+            text: String::new(),
+            spans: Vec::new(),
+            sources: Vec::new(),
+        };
+        let mut pending_space = None;
+        for (offset, ch) in raw.char_indices() {
+            let source = start + offset;
+            let span = span_at(source);
+            if collapse && ch.is_whitespace() {
+                if !paragraph.text.is_empty() {
+                    pending_space.get_or_insert((span, source));
+                }
+                continue;
+            }
+            if let Some((space_span, space_source)) = pending_space.take() {
+                paragraph.push(' ', space_span, space_source);
+            }
+            paragraph.push(ch, span, source);
+        }
+        paragraph.spans.push(span_at(start + raw.len()));
+        paragraph.sources.push(start + raw.len());
+        paragraphs.push(paragraph);
+        start += raw.len() + 1;
+    }
+    paragraphs
+}
+
+/// Shapes each paragraph once, breaks it at Unicode line break opportunities across span
+/// boundaries and reorders every line visually, so line widths are the advances of the glyphs
+/// that are drawn. Lines past `max_lines` are dropped, ending the last visible one with the
+/// ellipsis, as are lines that overflow `max_width`.
+fn layout_lines(text: &str, spans: &[TextSpan<'_>], options: &TextLayoutOptions) -> Vec<LineLayout> {
+    let mut lines = Vec::new();
+    let max_width = options.max_width;
+    let bounded = max_width.is_finite() && max_width > 0.0;
+    let wrap = options.white_space != WhiteSpace::Pre && bounded;
+    let max_lines = options.max_lines.unwrap_or(usize::MAX);
+    let collapse = options.white_space == WhiteSpace::Normal;
+    let paragraphs = split_paragraphs(text, spans, collapse);
+
+    for (paragraph_index, paragraph) in paragraphs.iter().enumerate() {
+        let shaped = shape_paragraph(paragraph, spans, options);
+
+        let ranges = if wrap {
+            let break_words = options.overflow_wrap == OverflowWrap::BreakWord;
+            break_lines(&shaped, max_width, break_words)
+        } else {
+            std::iter::once(0..paragraph.text.len()).collect()
+        };
+
+        let ranges_len = ranges.len();
+        let remaining = max_lines - lines.len();
+        let more_paragraphs = paragraph_index + 1 < paragraphs.len();
+        let truncated = ranges.len() > remaining || (ranges.len() == remaining && more_paragraphs);
+        let visible = ranges.len().min(remaining);
+        for (index, range) in ranges.into_iter().take(visible).enumerate() {
+            let last = index + 1 == visible;
+            let overflows = bounded && shaped.width(range.start, range.end) > max_width;
+            let fallback = shaped.spans[range.start];
+            let mut line = match &options.overflow {
+                TextOverflow::Ellipsis(ellipsis) if overflows || (last && truncated) => {
+                    let fit = if bounded { max_width } else { f32::INFINITY };
+                    ellipsize(&shaped, range, ellipsis, fit, spans, options)
+                }
+                _ => {
+                    let mut line = shaped.line(range);
+                    line.justifiable = wrap && index + 1 < ranges_len;
+                    line
+                }
+            };
+            line.measure(spans, fallback, options.vertical().is_some());
+            lines.push(line);
+        }
+        if lines.len() >= max_lines {
+            break;
+        }
+    }
+
+    lines
+}
+
+fn shape_paragraph<'t>(
+    paragraph: &'t Paragraph,
+    spans: &[TextSpan<'_>],
+    options: &TextLayoutOptions,
+) -> ShapedParagraph<'t> {
+    let text = paragraph.text.as_str();
+    let direction = options.direction;
+    let bidi = BidiInfo::new(text, direction.default_level());
+    let level = bidi.paragraphs.first().map_or_else(
+        || direction.default_level().unwrap_or_else(Level::ltr),
+        |para| para.level,
+    );
+    let mut glyphs = Vec::new();
+    for (index, range) in paragraph.span_runs() {
+        let span = &spans[index];
+        let mut run = span
+            .chain
+            .shape(text, range, &bidi.levels, index, options.vertical());
+        apply_spacing(&mut run, span.letter_spacing, options.word_spacing);
+        glyphs.append(&mut run);
+    }
+
+    // Prefix sums of glyph advances by cluster byte offset.
+    let mut offsets = vec![0.0f32; text.len() + 1];
+    for glyph in &glyphs {
+        offsets[glyph.cluster + 1] += glyph.advance;
+    }
+    for index in 1..offsets.len() {
+        offsets[index] += offsets[index - 1];
+    }
+
+    ShapedParagraph {
+        text,
+        spans: &paragraph.spans,
+        sources: &paragraph.sources,
+        bidi,
+        level,
+        glyphs,
+        offsets,
+    }
+}
+
+/// Builds a cut-off line from as much of `range` as fits in `max_width` alongside
+/// `ellipsis`, which takes the style of the span the line ends in and is placed at the
+/// paragraph's end side.
+fn ellipsize(
+    paragraph: &ShapedParagraph<'_>,
+    range: Range<usize>,
+    ellipsis: &str,
+    max_width: f32,
+    spans: &[TextSpan<'_>],
+    options: &TextLayoutOptions,
+) -> LineLayout {
+    let level = paragraph.level;
+    let span_index = paragraph.spans[range.end.saturating_sub(1).max(range.start)];
+    let span = &spans[span_index];
+    let levels = vec![level; ellipsis.len()];
+    let mut ellipsis_glyphs = span.chain.shape(
+        ellipsis,
+        0..ellipsis.len(),
+        &levels,
+        span_index,
+        options.vertical(),
+    );
+    apply_spacing(&mut ellipsis_glyphs, span.letter_spacing, options.word_spacing);
+    let ellipsis_width: f32 = ellipsis_glyphs.iter().map(|glyph| glyph.advance).sum();
+    let available = max_width - ellipsis_width;
+
+    let mut end = range.start;
+    for (offset, grapheme) in paragraph.text[range.clone()].grapheme_indices(true) {
+        let boundary = range.start + offset + grapheme.len();
+        if paragraph.width(range.start, boundary) > available {
+            break;
+        }
+        end = boundary;
+    }
+    let end = paragraph.trim_end(range.start, end);
+    for glyph in &mut ellipsis_glyphs {
+        glyph.cluster = paragraph.sources[end];
+    }
+
+    let mut line = paragraph.line(range.start..end);
+    if level.is_rtl() {
+        ellipsis_glyphs.append(&mut line.glyphs);
+        line.glyphs = ellipsis_glyphs;
+    } else {
+        line.glyphs.append(&mut ellipsis_glyphs);
+    }
+    line.width += ellipsis_width;
+    line.text.push_str(ellipsis);
+    line
+}
+
+/// A paragraph shaped as a whole, with its bidi levels and the advance of every cluster.
+struct ShapedParagraph<'t> {
+    text: &'t str,
+    /// Span of every byte of `text`, followed by the span at the paragraph end.
+    spans: &'t [usize],
+    /// Offset in the laid out text of every byte of `text`, followed by the paragraph end.
+    sources: &'t [usize],
+    bidi: BidiInfo<'t>,
+    level: Level,
+    glyphs: Vec<ShapedGlyph>,
+    offsets: Vec<f32>,
+}
+
+impl ShapedParagraph<'_> {
+    fn trim_end(&self, start: usize, end: usize) -> usize {
+        start + self.text[start..end].trim_end().len()
+    }
+
+    /// Width of `start..end`, ignoring trailing whitespace that hangs past the line end.
+    fn width(&self, start: usize, end: usize) -> f32 {
+        self.offsets[self.trim_end(start, end)] - self.offsets[start]
+    }
+
+    /// Collects the glyphs of `range` in visual order, with clusters as offsets into the laid
+    /// out text.
+    fn line(&self, range: Range<usize>) -> LineLayout {
+        let rtl = self.level.is_rtl();
+        let text = self.text[range.clone()].to_string();
+        let source = self.sources[range.start]..self.sources[range.end];
+        let Some(para) = self.bidi.paragraphs.first() else {
+            return LineLayout::new(text, source, Vec::new(), rtl);
+        };
+        if range.is_empty() {
+            return LineLayout::new(text, source, Vec::new(), rtl);
+        }
+        let (_, runs) = self.bidi.visual_runs(para, range);
+        let mut visual: Vec<ShapedGlyph> = runs
+            .into_iter()
+            .flat_map(|run| {
+                let rtl = self.bidi.levels[run.start].is_rtl();
+                glyphs_in(&self.glyphs, run, rtl)
+            })
+            .collect();
+        for glyph in &mut visual {
+            glyph.cluster = self.sources[glyph.cluster];
+        }
+        LineLayout::new(text, source, visual, rtl)
+    }
+}
+
+/// Breaks a paragraph into line ranges that fit `max_width` using UAX #14 break opportunities.
+/// Trailing whitespace hangs past the end of a line and is left out of its range. Words wider
+/// than a line overflow unless `break_words` is set, in which case they are split between
+/// graphemes.
+fn break_lines(shaped: &ShapedParagraph<'_>, max_width: f32, break_words: bool) -> Vec<Range<usize>> {
+    let paragraph = shaped.text;
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut last_fit = None;
+    for (position, opportunity) in linebreaks(paragraph) {
+        if shaped.width(start, position) > max_width {
+            if let Some(fit) = last_fit.take() {
+                ranges.push(start..shaped.trim_end(start, fit));
+                start = fit;
+            }
+            if break_words {
+                while shaped.width(start, position) > max_width {
+                    let fits = |end| shaped.width(start, end) <= max_width;
+                    let Some(end) = grapheme_break(paragraph, start, position, fits) else {
+                        break;
+                    };
+                    ranges.push(start..end);
+                    start = end;
+                }
+            }
+        }
+        if opportunity == BreakOpportunity::Mandatory {
+            ranges.push(start..shaped.trim_end(start, position));
+            start = position;
+            last_fit = None;
+        } else {
+            last_fit = Some(position);
+        }
+    }
+    if start < paragraph.len() || ranges.is_empty() {
+        ranges.push(start..shaped.trim_end(start, paragraph.len()));
+    }
+    ranges
+}
+
+/// Finds the last grapheme boundary in `start..end` for which `fits` holds, taking at least
+/// one grapheme so every line makes progress. Returns `None` if `start..end` is one grapheme.
+fn grapheme_break(paragraph: &str, start: usize, end: usize, fits: impl Fn(usize) -> bool) -> Option<usize> {
+    let mut boundaries = paragraph[start..end]
+        .grapheme_indices(true)
+        .map(|(offset, grapheme)| start + offset + grapheme.len())
+        .filter(|&boundary| boundary < end);
+    let first = boundaries.next()?;
+    Some(
+        boundaries
+            .take_while(|&boundary| fits(boundary))
+            .last()
+            .unwrap_or(first),
+    )
+}
+
+/// Collects the glyphs of a level run in visual order. Runs shaped separately, for different
+/// spans or faces, are stored in logical order, so right-to-left runs are reordered by
+/// descending cluster; the stable sort keeps the shaper's order within a cluster.
+fn glyphs_in(glyphs: &[ShapedGlyph], range: Range<usize>, rtl: bool) -> Vec<ShapedGlyph> {
+    let mut glyphs: Vec<ShapedGlyph> = glyphs
+        .iter()
+        .filter(|glyph| range.contains(&glyph.cluster))
+        .copied()
+        .collect();
+    if rtl {
+        glyphs.sort_by_key(|glyph| Reverse(glyph.cluster));
+    } else {
+        glyphs.sort_by_key(|glyph| glyph.cluster);
+    }
+    glyphs
+}
+
+/// Adds letter spacing after the last glyph of every cluster and word spacing after separators.
+fn apply_spacing(glyphs: &mut [ShapedGlyph], letter_spacing: f32, word_spacing: f32) {
+    let letter_spacing = finite_or_zero(letter_spacing);
+    let word_spacing = finite_or_zero(word_spacing);
+    if letter_spacing == 0.0 && word_spacing == 0.0 {
+        return;
+    }
+    for index in 0..glyphs.len() {
+        let cluster_end = glyphs
+            .get(index + 1)
+            .is_none_or(|next| next.cluster != glyphs[index].cluster);
+        let glyph = &mut glyphs[index];
+        if cluster_end {
+            glyph.advance += letter_spacing;
+        }
+        if glyph.separator {
+            glyph.advance += word_spacing;
+        }
+    }
+}
+
+fn finite_or_zero(value: f32) -> f32 {
+    if value.is_finite() {
+        value
+    } else {
+        0.0
+    }
+}
+
+/// Word separators per CSS Text, which receive `word_spacing` and stretch under `Justify`.
+fn is_word_separator(ch: char) -> bool {
+    matches!(
+        ch,
+        ' ' | '\u{A0}' | '\u{1361}' | '\u{10100}' | '\u{10101}' | '\u{1039F}' | '\u{1091F}'
+    )
+}
+
+/// Characters that never need a glyph of their own, such as joiners and variation selectors.
+fn is_default_ignorable(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{00AD}' | '\u{200B}'..='\u{200F}' | '\u{2060}'..='\u{2064}' | '\u{FE00}'..='\u{FE0F}' | '\u{FEFF}'
+    ) || ('\u{E0000}'..='\u{E0FFF}').contains(&ch)
+}
+
+/// Whether `grapheme` is presented as emoji, `Some(true)`, or as text, `Some(false)`. Only a
+/// text presentation selector asks for text; emoji presentation selectors, keycaps and
+/// characters that default to emoji presentation, such as regional indicator flags, ask for
+/// emoji. Other graphemes take any face.
+fn prefers_color(grapheme: &str) -> Option<bool> {
+    if grapheme.contains('\u{FE0E}') {
+        Some(false)
+    } else if grapheme.contains(['\u{FE0F}', '\u{20E3}']) || grapheme.chars().any(is_emoji_presentation) {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// Characters with the Unicode `Emoji_Presentation` property, drawn as emoji without a
+/// variation selector.
+fn is_emoji_presentation(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{231A}'..='\u{231B}'
+            | '\u{23E9}'..='\u{23EC}'
+            | '\u{23F0}'
+            | '\u{23F3}'
+            | '\u{25FD}'..='\u{25FE}'
+            | '\u{2614}'..='\u{2615}'
+            | '\u{2648}'..='\u{2653}'
+            | '\u{267F}'
+            | '\u{2693}'
+            | '\u{26A1}'
+            | '\u{26AA}'..='\u{26AB}'
+            | '\u{26BD}'..='\u{26BE}'
+            | '\u{26C4}'..='\u{26C5}'
+            | '\u{26CE}'
+            | '\u{26D4}'
+            | '\u{26EA}'
+            | '\u{26F2}'..='\u{26F3}'
+            | '\u{26F5}'
+            | '\u{26FA}'
+            | '\u{26FD}'
+            | '\u{2705}'
+            | '\u{270A}'..='\u{270B}'
+            | '\u{2728}'
+            | '\u{274C}'
+            | '\u{274E}'
+            | '\u{2753}'..='\u{2755}'
+            | '\u{2757}'
+            | '\u{2795}'..='\u{2797}'
+            | '\u{27B0}'
+            | '\u{27BF}'
+            | '\u{2B1B}'..='\u{2B1C}'
+            | '\u{2B50}'
+            | '\u{2B55}'
+            | '\u{1F004}'
+            | '\u{1F0CF}'
+            | '\u{1F18E}'
+            | '\u{1F191}'..='\u{1F19A}'
+            | '\u{1F1E6}'..='\u{1F1FF}'
+            | '\u{1F201}'
+            | '\u{1F21A}'
+            | '\u{1F22F}'
+            | '\u{1F232}'..='\u{1F236}'
+            | '\u{1F238}'..='\u{1F23A}'
+            | '\u{1F250}'..='\u{1F251}'
+            | '\u{1F300}'..='\u{1F320}'
+            | '\u{1F32D}'..='\u{1F335}'
+            | '\u{1F337}'..='\u{1F37C}'
+            | '\u{1F37E}'..='\u{1F393}'
+            | '\u{1F3A0}'..='\u{1F3CA}'
+            | '\u{1F3CF}'..='\u{1F3D3}'
+            | '\u{1F3E0}'..='\u{1F3F0}'
+            | '\u{1F3F4}'
+            | '\u{1F3F8}'..='\u{1F43E}'
+            | '\u{1F440}'
+            | '\u{1F442}'..='\u{1F4FC}'
+            | '\u{1F4FF}'..='\u{1F53D}'
+            | '\u{1F54B}'..='\u{1F54E}'
+            | '\u{1F550}'..='\u{1F567}'
+            | '\u{1F57A}'
+            | '\u{1F595}'..='\u{1F596}'
+            | '\u{1F5A4}'
+            | '\u{1F5FB}'..='\u{1F64F}'
+            | '\u{1F680}'..='\u{1F6C5}'
+            | '\u{1F6CC}'
+            | '\u{1F6D0}'..='\u{1F6D2}'
+            | '\u{1F6D5}'..='\u{1F6D7}'
+            | '\u{1F6DC}'..='\u{1F6DF}'
+            | '\u{1F6EB}'..='\u{1F6EC}'
+            | '\u{1F6F4}'..='\u{1F6FC}'
+            | '\u{1F7E0}'..='\u{1F7EB}'
+            | '\u{1F7F0}'
+            | '\u{1F90C}'..='\u{1F93A}'
+            | '\u{1F93C}'..='\u{1F945}'
+            | '\u{1F947}'..='\u{1F9FF}'
+            | '\u{1FA70}'..='\u{1FA7C}'
+            | '\u{1FA80}'..='\u{1FA88}'
+            | '\u{1FA90}'..='\u{1FABD}'
+            | '\u{1FABF}'..='\u{1FAC5}'
+            | '\u{1FACE}'..='\u{1FADB}'
+            | '\u{1FAE0}'..='\u{1FAE8}'
+            | '\u{1FAF0}'..='\u{1FAF8}'
+    )
+}
+
+/// Characters set upright in mixed vertical text: the blocks UAX #50 gives an upright
+/// orientation, such as Han, kana, Hangul, fullwidth forms and emoji. Punctuation in these
+/// blocks is turned into its vertical form by the font's `vert` feature.
+fn is_upright(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{00A7}'
+            | '\u{00A9}'
+            | '\u{00AE}'
+            | '\u{00B1}'
+            | '\u{00BC}'..='\u{00BE}'
+            | '\u{00D7}'
+            | '\u{00F7}'
+            | '\u{1100}'..='\u{11FF}'
+            | '\u{2E80}'..='\u{2FFF}'
+            | '\u{3000}'..='\u{30FF}'
+            | '\u{3100}'..='\u{31FF}'
+            | '\u{3200}'..='\u{A4CF}'
+            | '\u{A960}'..='\u{A97F}'
+            | '\u{AC00}'..='\u{D7FF}'
+            | '\u{E000}'..='\u{FAFF}'
+            | '\u{FE10}'..='\u{FE1F}'
+            | '\u{FE30}'..='\u{FE4F}'
+            | '\u{FF00}'..='\u{FF60}'
+            | '\u{FFE0}'..='\u{FFE7}'
+            | '\u{1F000}'..='\u{1FAFF}'
+            | '\u{20000}'..='\u{3FFFD}'
+    )
+}
+
+fn align_offset(align: TextAlign, max_width: f32, line: &LineLayout) -> f32 {
+    let width = if max_width.is_finite() && max_width > 0.0 {
+        max_width
+    } else {
+        line.width
+    };
+    match (align, line.rtl) {
+        (TextAlign::Start | TextAlign::Justify, false) | (TextAlign::End, true) => 0.0,
+        (TextAlign::Center, _) => (width - line.width) * 0.5,
+        (TextAlign::End, false) | (TextAlign::Start | TextAlign::Justify, true) => width - line.width,
+    }
+}
+
+/// A line positioned relative to the origin of its text node.
+pub(crate) struct PlacedLine {
+    pub(crate) line: LineLayout,
+    pub(crate) x: f32,
+    pub(crate) baseline: f32,
+}
+
+impl PlacedLine {
+    fn top(&self) -> f32 {
+        self.baseline - self.line.ascent
+    }
+
+    /// Splits the line into the graphemes of `text` its glyphs draw. Shaper clusters that
+    /// cover several graphemes, such as ligatures, are divided evenly between them, and the
+    /// ellipsis, which covers no text, is left out.
+    fn graphemes(&self, text: &str) -> Vec<GraphemeBox> {
+        let glyphs = &self.line.glyphs;
+        let mut starts: Vec<usize> = glyphs.iter().map(|glyph| glyph.cluster).collect();
+        starts.sort_unstable();
+        starts.dedup();
+        let cluster_end = |cluster: usize| {
+            let next = starts.iter().copied().find(|&start| start > cluster);
+            next.unwrap_or(self.line.range.end).max(cluster)
+        };
+
+        let mut boxes = Vec::new();
+        let mut x = self.x;
+        let mut index = 0;
+        while index < glyphs.len() {
+            let first = glyphs[index];
+            let mut width = 0.0;
+            while index < glyphs.len() && glyphs[index].cluster == first.cluster {
+                width += glyphs[index].advance;
+                index += 1;
+            }
+            let range = first.cluster..cluster_end(first.cluster);
+            let graphemes: Vec<Range<usize>> = text
+                .get(range.clone())
+                .unwrap_or_default()
+                .grapheme_indices(true)
+                .map(|(offset, grapheme)| {
+                    let start = range.start + offset;
+                    start..start + grapheme.len()
+                })
+                .collect();
+            let share = width / graphemes.len().max(1) as f32;
+            let count = graphemes.len();
+            for (order, range) in graphemes.into_iter().enumerate() {
+                let slot = if first.rtl { count - 1 - order } else { order };
+                let x0 = x + share * slot as f32;
+                boxes.push(GraphemeBox {
+                    range,
+                    x0,
+                    x1: x0 + share,
+                    rtl: first.rtl,
+                });
+            }
+            x += width;
+        }
+        boxes
+    }
+}
+
+/// A grapheme of a placed line and the horizontal extent of its glyphs, relative to the text
+/// origin.
+struct GraphemeBox {
+    range: Range<usize>,
+    x0: f32,
+    x1: f32,
+    rtl: bool,
+}
+
+impl GraphemeBox {
+    /// Caret position before the grapheme in logical order.
+    fn leading(&self) -> f32 {
+        if self.rtl {
+            self.x1
+        } else {
+            self.x0
+        }
+    }
+
+    /// Caret position after the grapheme in logical order.
+    fn trailing(&self) -> f32 {
+        if self.rtl {
+            self.x0
+        } else {
+            self.x1
+        }
+    }
+
+    fn distance(&self, x: f32) -> f32 {
+        (self.x0 - x).max(x - self.x1).max(0.0)
+    }
+}
+
+/// Laid out lines of a `Text` or `RichText` node, shared by drawing, `measure_text` and the
+/// hit-testing queries. Lines are laid out horizontally and stacked downwards in the layout's
+/// frame, which `frame` maps onto the text's coordinates.
+pub(crate) struct TextLayout {
+    pub(crate) lines: Vec<PlacedLine>,
+    pub(crate) decorations: Vec<DecorationLine>,
+    pub(crate) vertical: bool,
+}
+
+impl TextLayout {
+    /// Transform from the line frame to text coordinates. Vertical lines become columns that
+    /// run down from the top of the block and stack leftwards from its right edge.
+    pub(crate) fn frame(&self) -> Affine {
+        if self.vertical {
+            Affine::new([0.0, 1.0, -1.0, 0.0, self.height() as f64, 0.0])
+        } else {
+            Affine::IDENTITY
+        }
+    }
+
+    /// Union of the line boxes, from each line's ascent to its descent.
+    pub(crate) fn bounds(&self) -> Rect {
+        self.lines
+            .iter()
+            .map(|placed| {
+                Rect::new(
+                    placed.x as f64,
+                    (placed.baseline - placed.line.ascent) as f64,
+                    (placed.x + placed.line.width) as f64,
+                    (placed.baseline - placed.line.descent) as f64,
+                )
+            })
+            .reduce(|bounds, line_box| bounds.union(line_box))
+            .unwrap_or(Rect::ZERO)
+    }
+
+    /// Total advance of the laid out lines.
+    pub(crate) fn height(&self) -> f32 {
+        self.lines.iter().map(|placed| placed.line.height).sum()
+    }
+
+    /// Offset in the laid out `text` of the grapheme boundary nearest to `(x, y)`, relative
+    /// to the text origin. Points above or below the lines resolve to the first or last line.
+    pub(crate) fn hit_test(&self, text: &str, x: f32, y: f32) -> usize {
+        let Some(placed) = self
+            .lines
+            .iter()
+            .find(|placed| y < placed.top() + placed.line.height)
+            .or(self.lines.last())
+        else {
+            return 0;
+        };
+        let boxes = placed.graphemes(text);
+        let Some(nearest) = boxes
+            .iter()
+            .min_by(|a, b| a.distance(x).total_cmp(&b.distance(x)))
+        else {
+            return placed.line.range.start;
+        };
+        let before = x < (nearest.x0 + nearest.x1) * 0.5;
+        if before != nearest.rtl {
+            nearest.range.start
+        } else {
+            nearest.range.end
+        }
+    }
+
+    /// Caret before the byte at `index` in the laid out `text`, as a zero-width rectangle the
+    /// height of its line. An index at a soft line break is placed at the start of the next
+    /// line and one inside a grapheme at the start of that grapheme.
+    pub(crate) fn caret(&self, text: &str, index: usize) -> Rect {
+        let Some(placed) = self
+            .lines
+            .iter()
+            .rev()
+            .find(|placed| placed.line.range.start <= index)
+            .or(self.lines.first())
+        else {
+            return Rect::ZERO;
+        };
+        let boxes = placed.graphemes(text);
+        let logical_first = || boxes.iter().min_by_key(|grapheme| grapheme.range.start);
+        let logical_last = || boxes.iter().max_by_key(|grapheme| grapheme.range.end);
+        let x = boxes
+            .iter()
+            .find(|grapheme| grapheme.range.start == index)
+            .map(GraphemeBox::leading)
+            .or_else(|| {
+                boxes
+                    .iter()
+                    .find(|grapheme| grapheme.range.end == index)
+                    .map(GraphemeBox::trailing)
+            })
+            .or_else(|| {
+                boxes
+                    .iter()
+                    .find(|grapheme| grapheme.range.contains(&index))
+                    .map(GraphemeBox::leading)
+            })
+            .or_else(|| {
+                if index < placed.line.range.start {
+                    logical_first().map(GraphemeBox::leading)
+                } else {
+                    logical_last().map(GraphemeBox::trailing)
+                }
+            })
+            .unwrap_or(placed.x);
+        let top = placed.top();
+        Rect::new(x as f64, top as f64, x as f64, (top + placed.line.height) as f64)
+    }
+
+    /// Rectangles covering every grapheme of the laid out `text` that `range` touches, one
+    /// per visually contiguous stretch of each line.
+    pub(crate) fn selection(&self, text: &str, range: Range<usize>) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        for placed in &self.lines {
+            let mut extents: Vec<(f32, f32)> = placed
+                .graphemes(text)
+                .iter()
+                .filter(|grapheme| grapheme.range.start < range.end && range.start < grapheme.range.end)
+                .map(|grapheme| (grapheme.x0, grapheme.x1))
+                .collect();
+            extents.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let top = placed.top();
+            let bottom = top + placed.line.height;
+            let mut stretch: Option<(f32, f32)> = None;
+            for (x0, x1) in extents {
+                match &mut stretch {
+                    Some((_, end)) if x0 <= *end + 1e-3 => *end = end.max(x1),
+                    _ => {
+                        if let Some((start, end)) = stretch.replace((x0, x1)) {
+                            rects.push(Rect::new(start as f64, top as f64, end as f64, bottom as f64));
+                        }
+                    }
+                }
+            }
+            if let Some((start, end)) = stretch {
+                rects.push(Rect::new(start as f64, top as f64, end as f64, bottom as f64));
+            }
+        }
+        rects
+    }
+}
+
+/// Lays out `text` styled by `spans` as one block. Each line advances by the tallest line
+/// height among its spans, with its baseline one line ascent below its top.
+pub(crate) fn layout_text(
+    text: &str,
+    spans: &[TextSpan<'_>],
+    align: TextAlign,
+    options: &TextLayoutOptions,
+) -> TextLayout {
+    if spans.is_empty() {
+        return TextLayout {
+            lines: Vec::new(),
+            decorations: Vec::new(),
+            vertical: false,
+        };
+    }
+    let vertical = options.vertical().is_some();
+    let mut top = 0.0;
+    let lines: Vec<PlacedLine> = layout_lines(text, spans, options)
+        .into_iter()
+        .map(|mut line| {
+            if align == TextAlign::Justify {
+                line.justify(options.max_width);
+            }
+            let baseline = top + line.ascent;
+            top += line.height;
+            PlacedLine {
+                x: align_offset(align, options.max_width, &line),
+                baseline,
+                line,
+            }
+        })
+        .collect();
+    let mut decorations = Vec::new();
+    for placed in &lines {
+        decorate_line(placed, spans, vertical, &mut decorations);
+    }
+    TextLayout {
+        lines,
+        decorations,
+        vertical,
+    }
+}
+
+/// Adds the decoration lines of every span on `placed`. Lines sit at the positions given by
+/// the span's primary font, and underlines are interrupted where glyphs cross them. In
+/// vertical text, underlines and overlines run along the left and right edges of the em box
+/// centred on the line, and line-throughs along its centre.
+fn decorate_line(
+    placed: &PlacedLine,
+    spans: &[TextSpan<'_>],
+    vertical: bool,
+    decorations: &mut Vec<DecorationLine>,
+) {
+    // Visually contiguous glyphs of one span, with the pen position they start at.
+    let mut segments: Vec<(usize, f32, f32, Range<usize>)> = Vec::new();
+    let mut x = placed.x;
+    for (index, glyph) in placed.line.glyphs.iter().enumerate() {
+        match segments.last_mut() {
+            Some((span, _, end, glyphs)) if *span == glyph.span => {
+                *end = x + glyph.advance;
+                glyphs.end = index + 1;
+            }
+            _ => segments.push((glyph.span, x, x + glyph.advance, index..index + 1)),
+        }
+        x += glyph.advance;
+    }
+
+    let baseline = placed.baseline;
+    for (span_index, x0, x1, glyphs) in segments {
+        let span = &spans[span_index];
+        let decoration = span.decoration;
+        let chain = &span.chain;
+        let half_em = (chain.ascent - chain.descent) * 0.5;
+        let mut push = |kind, y: f32, thickness: f32, x0: f32, x1: f32| {
+            if x1 > x0 {
+                decorations.push(DecorationLine {
+                    span: span_index,
+                    kind,
+                    style: decoration.style,
+                    x0,
+                    x1,
+                    y,
+                    thickness,
+                });
+            }
+        };
+        if decoration.underline {
+            let thickness = decoration.thickness(chain.underline.thickness);
+            let y = if vertical {
+                baseline + half_em - thickness * 0.5
+            } else {
+                baseline - chain.underline.offset + thickness * 0.5
+            };
+            let reach = match decoration.style {
+                DecorationStyle::Wavy => thickness * 1.5,
+                _ => thickness * 0.5,
+            };
+            let mut gaps = chain.ink_extents(
+                &placed.line.glyphs[glyphs],
+                x0,
+                baseline,
+                y - reach - thickness,
+                y + reach + thickness,
+            );
+            gaps.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let mut start = x0;
+            for (left, right) in gaps {
+                push(DecorationKind::Underline, y, thickness, start, left - thickness);
+                start = start.max(right + thickness);
+            }
+            push(DecorationKind::Underline, y, thickness, start, x1);
+        }
+        if decoration.overline {
+            let thickness = decoration.thickness(chain.underline.thickness);
+            let y = if vertical {
+                baseline - half_em + thickness * 0.5
+            } else {
+                baseline - chain.ascent + thickness * 0.5
+            };
+            push(DecorationKind::Overline, y, thickness, x0, x1);
+        }
+        if decoration.line_through {
+            let thickness = decoration.thickness(chain.strikeout.thickness);
+            let y = if vertical {
+                baseline
+            } else {
+                baseline - chain.strikeout.offset + thickness * 0.5
+            };
+            push(DecorationKind::LineThrough, y, thickness, x0, x1);
+        }
+    }
+}
+
+/// Collects a glyph outline as a kurbo path.
+struct OutlinePath(BezPath);
+
+impl OutlinePen for OutlinePath {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.0.move_to((x as f64, y as f64));
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.0.line_to((x as f64, y as f64));
+    }
+
+    fn quad_to(&mut self, cx0: f32, cy0: f32, x: f32, y: f32) {
+        self.0.quad_to((cx0 as f64, cy0 as f64), (x as f64, y as f64));
+    }
+
+    fn curve_to(&mut self, cx0: f32, cy0: f32, cx1: f32, cy1: f32, x: f32, y: f32) {
+        self.0.curve_to(
+            (cx0 as f64, cy0 as f64),
+            (cx1 as f64, cy1 as f64),
+            (x as f64, y as f64),
+        );
+    }
+
+    fn close(&mut self) {
+        self.0.close_path();
+    }
+}
+
+/// Horizontal extent of the parts of `path` that lie within `y0..y1`.
+fn band_extent(path: &BezPath, y0: f64, y1: f64) -> Option<(f64, f64)> {
+    let mut extent: Option<(f64, f64)> = None;
+    let mut start = Point::ZERO;
+    let mut last = Point::ZERO;
+    flatten(path.iter(), 0.25, |element| {
+        let (from, to) = match element {
+            PathEl::MoveTo(point) => {
+                start = point;
+                last = point;
+                return;
+            }
+            PathEl::LineTo(point) => (mem::replace(&mut last, point), point),
+            PathEl::ClosePath => (mem::replace(&mut last, start), start),
+            _ => return,
+        };
+        if from.y.max(to.y) < y0 || from.y.min(to.y) > y1 {
+            return;
+        }
+        let (left, right) = if from.y == to.y {
+            (from.x.min(to.x), from.x.max(to.x))
+        } else {
+            let x_at = |y: f64| from.x + (to.x - from.x) * ((y - from.y) / (to.y - from.y)).clamp(0.0, 1.0);
+            let (a, b) = (x_at(y0), x_at(y1));
+            (a.min(b), a.max(b))
+        };
+        extent = Some(match extent {
+            Some((min, max)) => (min.min(left), max.max(right)),
+            None => (left, right),
+        });
+    });
+    extent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fonts::{FontQuery, FontRegistry, FontStyle};
+
+    /// The bundled fonts at one size.
+    struct Bundled {
+        fonts: Vec<FontInstance>,
+        size: f32,
+    }
+
+    fn bundled(size: f32) -> Bundled {
+        Bundled {
+            fonts: FontRegistry::new().resolve_chain(&FontQuery {
+                family: String::new(),
+                weight: 400,
+                width: 100.0,
+                style: FontStyle::Normal,
+                slant: 14.0,
+                variations: Vec::new(),
+            }),
+            size,
+        }
+    }
+
+    impl Bundled {
+        /// A single span styling all of `text`.
+        fn spans(&self, text: &str, letter_spacing: f32) -> Vec<TextSpan<'_>> {
+            vec![TextSpan {
+                letter_spacing,
+                ..self.span(0..text.len())
+            }]
+        }
+
+        /// An unspaced span styling `range`.
+        fn span(&self, range: Range<usize>) -> TextSpan<'_> {
+            TextSpan {
+                chain: FontChain::new(&self.fonts, self.size).unwrap(),
+                range,
+                letter_spacing: 0.0,
+                line_height: 0.0,
+                decoration: TextDecoration::default(),
+            }
+        }
+    }
+
+    fn layout(fonts: &Bundled, text: &str, max_width: f32, direction: TextDirection) -> Vec<LineLayout> {
+        let options = TextLayoutOptions { direction, ..TextLayoutOptions::test(max_width) };
+        layout_lines(text, &fonts.spans(text, 0.0), &options)
+    }
+
+    /// Lays `text` out on a single line.
+    fn line(fonts: &Bundled, text: &str) -> LineLayout {
+        layout(fonts, text, f32::INFINITY, TextDirection::Auto).remove(0)
+    }
+
+    fn advance(fonts: &Bundled, text: &str) -> f32 {
+        line(fonts, text).width
+    }
+
+    #[test]
+    fn kerning_tightens_pair_advances() {
+        let fonts = bundled(100.0);
+        assert!(advance(&fonts, "AV") < advance(&fonts, "A") + advance(&fonts, "V"));
+        assert!(advance(&fonts, "To") < advance(&fonts, "T") + advance(&fonts, "o"));
+    }
+
+    #[test]
+    fn ligatures_replace_their_characters() {
+        let fonts = bundled(100.0);
+        let glyphs = line(&fonts, "fi").glyphs;
+        assert_eq!(glyphs.len(), 1);
+        assert_eq!(glyphs[0].cluster, 0);
+        assert_ne!(glyphs[0].advance, advance(&fonts, "f") + advance(&fonts, "i"));
+    }
+
+    #[test]
+    fn line_widths_are_drawn_advances() {
+        let fonts = bundled(100.0);
+        let layout = line(&fonts, "AV fi");
+        let drawn: f32 = layout.glyphs.iter().map(|glyph| glyph.advance).sum();
+        assert_eq!(layout.width, drawn);
+        assert!(layout.width < advance(&fonts, "A") + advance(&fonts, "V fi"));
+    }
+
+    fn clusters(line: &LineLayout) -> Vec<usize> {
+        line.glyphs.iter().map(|glyph| glyph.cluster).collect()
+    }
+
+    #[test]
+    fn rtl_runs_are_drawn_in_visual_order() {
+        let fonts = bundled(16.0);
+
+        // Hebrew letters are two bytes each; the run reverses after the Latin prefix.
+        let ltr = layout(
+            &fonts,
+            "ab \u{5D0}\u{5D1}\u{5D2}",
+            f32::INFINITY,
+            TextDirection::Auto,
+        );
+        assert!(!ltr[0].rtl);
+        assert_eq!(clusters(&ltr[0]), [0, 1, 2, 7, 5, 3]);
+
+        // Resolved right to left from the first strong character, the Latin run moves left.
+        let rtl = layout(&fonts, "\u{5D0}\u{5D1} ab", f32::INFINITY, TextDirection::Auto);
+        assert!(rtl[0].rtl);
+        assert_eq!(clusters(&rtl[0]), [5, 6, 4, 2, 0]);
+    }
+
+    #[test]
+    fn start_and_end_follow_paragraph_direction() {
+        let fonts = bundled(16.0);
+        let ltr = layout(&fonts, "ab", f32::INFINITY, TextDirection::Ltr).remove(0);
+        let rtl = layout(&fonts, "ab", f32::INFINITY, TextDirection::Rtl).remove(0);
+        assert!(rtl.rtl);
+
+        let slack = 100.0 - ltr.width;
+        assert_eq!(align_offset(TextAlign::Start, 100.0, &ltr), 0.0);
+        assert_eq!(align_offset(TextAlign::End, 100.0, &ltr), slack);
+        assert_eq!(align_offset(TextAlign::Start, 100.0, &rtl), slack);
+        assert_eq!(align_offset(TextAlign::End, 100.0, &rtl), 0.0);
+        assert_eq!(align_offset(TextAlign::Center, 100.0, &rtl), slack * 0.5);
+    }
+
+    /// A paragraph of a single span, laid out from `text` unchanged.
+    fn paragraph(text: &str) -> Paragraph {
+        Paragraph {
+            text: text.to_string(),
+            spans: vec![0; text.len() + 1],
+            sources: (0..=text.len()).collect(),
+        }
+    }
+
+    /// A left-to-right paragraph with one glyph per character, each 10 units wide.
+    fn monospace(paragraph: &Paragraph) -> ShapedParagraph<'_> {
+        let text = paragraph.text.as_str();
+        let glyphs: Vec<ShapedGlyph> = text
+            .char_indices()
+            .map(|(cluster, ch)| ShapedGlyph {
+                cluster,
+                separator: is_word_separator(ch),
+                ..ShapedGlyph::test(1, 10.0)
+            })
+            .collect();
+        let offsets = (0..=text.len())
+            .map(|end| 10.0 * glyphs.iter().filter(|glyph| glyph.cluster < end).count() as f32)
+            .collect();
+        ShapedParagraph {
+            text,
+            spans: &paragraph.spans,
+            sources: &paragraph.sources,
+            bidi: BidiInfo::new(text, Some(Level::ltr())),
+            level: Level::ltr(),
+            glyphs,
+            offsets,
+        }
+    }
+
+    fn lines(text: &str, max_width: f32, break_words: bool) -> Vec<&str> {
+        break_lines(&monospace(&paragraph(text)), max_width, break_words)
+            .into_iter()
+            .map(|range| &text[range])
+            .collect()
+    }
+
+    #[test]
+    fn breaks_at_line_break_opportunities() {
+        assert_eq!(lines("hello world", 60.0, false), ["hello", "world"]);
+        assert_eq!(lines("well-known", 60.0, false), ["well-", "known"]);
+        // Ideographs break between any two characters.
+        assert_eq!(
+            lines("\u{65E5}\u{672C}\u{8A9E}\u{306E}\u{6587}", 25.0, false),
+            ["\u{65E5}\u{672C}", "\u{8A9E}\u{306E}", "\u{6587}"]
+        );
+    }
+
+    #[test]
+    fn breaks_long_words_only_when_asked() {
+        assert_eq!(lines("abcdefgh ij", 30.0, false), ["abcdefgh", "ij"]);
+        assert_eq!(lines("abcdefgh ij", 30.0, true), ["abc", "def", "gh", "ij"]);
+        // A combining mark stays with its base character.
+        assert_eq!(lines("abe\u{301}f", 30.0, true), ["ab", "e\u{301}f"]);
+    }
+
+    #[test]
+    fn trailing_spaces_hang_past_the_line_end() {
+        // "abc   " is 60 wide, but its spaces do not count against the 35 available.
+        assert_eq!(lines("abc   def", 35.0, false), ["abc", "def"]);
+        assert_eq!(lines("abc   ", 35.0, false), ["abc"]);
+    }
+
+    fn layout_with(fonts: &Bundled, text: &str, max_width: f32, white_space: WhiteSpace) -> Vec<usize> {
+        let options = TextLayoutOptions { white_space, ..TextLayoutOptions::test(max_width) };
+        let lines = layout_lines(text, &fonts.spans(text, 0.0), &options);
+        lines.iter().map(|line| line.glyphs.len()).collect()
+    }
+
+    #[test]
+    fn white_space_modes() {
+        let fonts = bundled(16.0);
+        let narrow = advance(&fonts, "ab") + 1.0;
+
+        // Normal collapses runs of spaces whether or not the text wraps.
+        assert_eq!(
+            layout_with(&fonts, "  ab   cd ", f32::INFINITY, WhiteSpace::Normal),
+            [5]
+        );
+        assert_eq!(layout_with(&fonts, "  ab   cd ", 1000.0, WhiteSpace::Normal), [5]);
+        assert_eq!(layout_with(&fonts, "ab   cd", narrow, WhiteSpace::Normal), [2, 2]);
+        // Pre-wrap keeps inner and leading spaces and still wraps; spaces at a break or at the
+        // end of the paragraph hang outside the line.
+        assert_eq!(
+            layout_with(&fonts, "  ab   cd ", 1000.0, WhiteSpace::PreWrap),
+            [9]
+        );
+        assert_eq!(
+            layout_with(&fonts, "ab   cd", narrow, WhiteSpace::PreWrap),
+            [2, 2]
+        );
+        // Pre keeps every space and never wraps.
+        assert_eq!(layout_with(&fonts, "ab   cd", narrow, WhiteSpace::Pre), [7]);
+    }
+
+    fn ellipsized(fonts: &Bundled, text: &str, max_width: f32, max_lines: Option<usize>) -> Vec<LineLayout> {
+        let options = TextLayoutOptions {
+            max_lines,
+            overflow: TextOverflow::Ellipsis("\u{2026}".to_string()),
+            ..TextLayoutOptions::test(max_width)
+        };
+        layout_lines(text, &fonts.spans(text, 0.0), &options)
+    }
+
+    fn ends_with_ellipsis(fonts: &Bundled, layout: &LineLayout) -> bool {
+        let ellipsis = line(fonts, "\u{2026}").glyphs[0].id;
+        layout.glyphs.last().is_some_and(|glyph| glyph.id == ellipsis)
+    }
+
+    #[test]
+    fn ellipsis_ends_the_last_visible_line() {
+        let fonts = bundled(16.0);
+        let max_width = advance(&fonts, "aa bb") + 1.0;
+
+        let lines = ellipsized(&fonts, "aa bb cc dd", max_width, Some(1));
+        assert_eq!(lines.len(), 1);
+        assert!(ends_with_ellipsis(&fonts, &lines[0]));
+        assert!(lines[0].width <= max_width);
+
+        // Nothing is cut off when every line is visible.
+        let lines = ellipsized(&fonts, "aa bb cc dd", max_width, Some(2));
+        assert_eq!(lines.len(), 2);
+        assert!(!lines.iter().any(|line| ends_with_ellipsis(&fonts, line)));
+    }
+
+    #[test]
+    fn ellipsis_ends_overflowing_middle_lines() {
+        let fonts = bundled(16.0);
+        let max_width = advance(&fonts, "abc") + 1.0;
+
+        let lines = ellipsized(&fonts, "ab abcdefghij cd", max_width, None);
+        assert_eq!(lines.len(), 3);
+        assert!(!ends_with_ellipsis(&fonts, &lines[0]));
+        assert!(ends_with_ellipsis(&fonts, &lines[1]));
+        assert!(lines[1].width <= max_width);
+        assert!(!ends_with_ellipsis(&fonts, &lines[2]));
+    }
+
+    #[test]
+    fn ellipsis_ends_unwrapped_lines_that_overflow() {
+        let fonts = bundled(16.0);
+        let max_width = advance(&fonts, "ab cd") - 1.0;
+        let options = TextLayoutOptions {
+            white_space: WhiteSpace::Pre,
+            overflow: TextOverflow::Ellipsis("\u{2026}".to_string()),
+            ..TextLayoutOptions::test(max_width)
+        };
+        let text = "ab cd\nef";
+        let lines = layout_lines(text, &fonts.spans(text, 0.0), &options);
+        assert_eq!(lines.len(), 2);
+        assert!(ends_with_ellipsis(&fonts, &lines[0]));
+        assert!(lines[0].width <= max_width);
+        assert!(!ends_with_ellipsis(&fonts, &lines[1]));
+    }
+
+    #[test]
+    fn ellipsis_keeps_clusters_and_ligatures_whole() {
+        let fonts = bundled(100.0);
+        let ligature = line(&fonts, "fi").glyphs[0].id;
+
+        // Room for two ligatures and the ellipsis, plus more than an "f" of the third.
+        let max_width =
+            2.0 * advance(&fonts, "fi") + advance(&fonts, "\u{2026}") + advance(&fonts, "f") + 1.0;
+        let lines = ellipsized(&fonts, "fifififi", max_width, Some(1));
+        let ids: Vec<u32> = lines[0].glyphs.iter().map(|glyph| glyph.id).collect();
+        assert_eq!(ids[..2], [ligature, ligature]);
+        assert_eq!(ids.len(), 3);
+        assert!(ends_with_ellipsis(&fonts, &lines[0]));
+
+        // Each "e" and its combining acute accent form one three-byte grapheme.
+        let text = "e\u{301}e\u{301}e\u{301}e\u{301}";
+        let max_width = advance(&fonts, "e\u{301}e\u{301}") + advance(&fonts, "\u{2026}") + 10.0;
+        let lines = ellipsized(&fonts, text, max_width, Some(1));
+        let line = &lines[0];
+        assert!(ends_with_ellipsis(&fonts, line));
+        assert!(line.glyphs.iter().all(|glyph| glyph.cluster % 3 == 0));
+        assert_eq!(line.glyphs.last().unwrap().cluster, 6);
+    }
+
+    fn spaced(max_width: f32, word_spacing: f32) -> TextLayoutOptions {
+        TextLayoutOptions { word_spacing, ..TextLayoutOptions::test(max_width) }
+    }
+
+    #[test]
+    fn justify_fills_every_line_but_the_last() {
+        let fonts = bundled(16.0);
+        let max_width = advance(&fonts, "aa bb cc") + 5.0;
+
+        let text = "aa bb cc dd ee";
+        let options = spaced(max_width, 0.0);
+        let layout = layout_text(text, &fonts.spans(text, 0.0), TextAlign::Justify, &options);
+        assert_eq!(layout.lines.len(), 2);
+        let first = &layout.lines[0];
+        assert_eq!(first.line.width, max_width);
+        assert_eq!(first.x, 0.0);
+        let drawn: f32 = first.line.glyphs.iter().map(|glyph| glyph.advance).sum();
+        assert!((drawn - max_width).abs() < 1e-3);
+
+        let last = &layout.lines[1];
+        assert_eq!(last.line.width, advance(&fonts, "dd ee"));
+        assert_eq!(last.x, 0.0);
+    }
+
+    #[test]
+    fn spacing_widens_lines_and_their_wrapping() {
+        let fonts = bundled(16.0);
+        let unspaced = advance(&fonts, "ab cd");
+
+        // Five clusters take letter spacing; the one separator also takes word spacing.
+        let lines = layout_lines("ab cd", &fonts.spans("ab cd", 2.0), &spaced(f32::INFINITY, 3.0));
+        assert!((lines[0].width - (unspaced + 5.0 * 2.0 + 3.0)).abs() < 1e-3);
+
+        // The spacing alone pushes the second word onto its own line.
+        let lines = layout_lines("ab cd", &fonts.spans("ab cd", 2.0), &spaced(unspaced + 1.0, 0.0));
+        assert_eq!(lines.len(), 2);
+    }
+
+    fn vertical(fonts: &Bundled, text: &str, orientation: TextOrientation) -> TextLayout {
+        let options = TextLayoutOptions {
+            writing_mode: WritingMode::VerticalRl,
+            orientation,
+            ..TextLayoutOptions::test(0.0)
+        };
+        layout_text(text, &fonts.spans(text, 0.0), TextAlign::Start, &options)
+    }
+
+    fn uprights(layout: &TextLayout) -> Vec<bool> {
+        layout.lines[0]
+            .line
+            .glyphs
+            .iter()
+            .map(|glyph| glyph.upright)
+            .collect()
+    }
+
+    #[test]
+    fn orientation_sets_glyphs_upright_or_sideways() {
+        let fonts = bundled(16.0);
+        let text = "ab\u{65E5}\u{672C}";
+        assert_eq!(
+            uprights(&vertical(&fonts, text, TextOrientation::Mixed)),
+            [false, false, true, true]
+        );
+        assert_eq!(
+            uprights(&vertical(&fonts, text, TextOrientation::Upright)),
+            [true; 4]
+        );
+        assert_eq!(
+            uprights(&vertical(&fonts, text, TextOrientation::Sideways)),
+            [false; 4]
+        );
+        // Horizontal text never sets glyphs upright.
+        assert!(line(&fonts, text).glyphs.iter().all(|glyph| !glyph.upright));
+    }
+
+    #[test]
+    fn vertical_advances_follow_glyph_orientation() {
+        let fonts = bundled(16.0);
+        let spans = fonts.spans("ab", 0.0);
+        let chain = &spans[0].chain;
+
+        // Sideways glyphs keep their horizontal advances and are centred on the column.
+        let sideways = vertical(&fonts, "ab", TextOrientation::Sideways);
+        let line = &sideways.lines[0].line;
+        assert_eq!(line.width, advance(&fonts, "ab"));
+        let centre = -(chain.ascent + chain.descent) * 0.5;
+        assert!(line.glyphs.iter().all(|glyph| glyph.y_offset == centre));
+
+        // Upright glyphs advance by the font's vertical metrics, here its ascent to descent
+        // for a font without vertical metrics tables.
+        let upright = vertical(&fonts, "ab", TextOrientation::Upright);
+        let line = &upright.lines[0].line;
+        assert!(line
+            .glyphs
+            .iter()
+            .all(|glyph| glyph.advance == chain.ascent - chain.descent));
+        assert_eq!(line.width, 2.0 * (chain.ascent - chain.descent));
+    }
+
+    #[test]
+    fn vertical_lines_stack_right_to_left() {
+        let fonts = bundled(16.0);
+        let layout = vertical(&fonts, "ab\ncd", TextOrientation::Mixed);
+        let column = |index: usize| {
+            let placed = &layout.lines[index];
+            assert_eq!(placed.line.ascent, -placed.line.descent);
+            (layout.frame() * Point::new(0.0, placed.baseline as f64)).x
+        };
+        assert!(column(0) > column(1));
+        assert!(column(1) > 0.0);
+    }
+
+    #[test]
+    fn bounds_hug_the_line_boxes() {
+        let fonts = bundled(16.0);
+        let text = "ab cd";
+        let max_width = advance(&fonts, text) + 40.0;
+        let layout = layout_text(text, &fonts.spans(text, 0.0), TextAlign::Center, &spaced(max_width, 0.0));
+        let placed = &layout.lines[0];
+        assert!((placed.x - 20.0).abs() < 1e-3);
+
+        // Centred text starts right of the origin, and its box starts with it.
+        let bounds = layout.bounds();
+        assert_eq!(bounds.min_x(), placed.x as f64);
+        assert_eq!(bounds.max_x(), (placed.x + placed.line.width) as f64);
+        assert_eq!(bounds.min_y(), (placed.baseline - placed.line.ascent) as f64);
+        assert_eq!(bounds.max_y(), (placed.baseline - placed.line.descent) as f64);
+    }
+
+    #[test]
+    fn lines_take_the_metrics_of_their_tallest_span() {
+        let small = bundled(16.0);
+        let large = bundled(32.0);
+        let text = "ab cd\nef";
+        let spans = [small.span(0..3), large.span(3..5), small.span(5..text.len())];
+        let layout = layout_text(text, &spans, TextAlign::Start, &TextLayoutOptions::test(0.0));
+        assert_eq!(layout.lines.len(), 2);
+
+        // The larger span sets the first line's ascent, descent and height.
+        let first = &layout.lines[0];
+        assert!(spans[1].chain.ascent > spans[0].chain.ascent);
+        assert_eq!(first.line.ascent, spans[1].chain.ascent);
+        assert_eq!(first.line.descent, spans[1].chain.descent);
+        assert_eq!(first.line.height, spans[1].line_height());
+        assert_eq!(first.baseline, first.line.ascent);
+
+        // The next line holds only small text, one large line height down.
+        let second = &layout.lines[1];
+        assert_eq!(second.line.ascent, spans[2].chain.ascent);
+        assert_eq!(second.line.descent, spans[2].chain.descent);
+        assert_eq!(second.line.height, spans[2].line_height());
+        assert_eq!(second.baseline, first.line.height + spans[2].chain.ascent);
+    }
+
+    /// Lays `text` out on one line with every decoration line drawn at the font's thickness.
+    fn decorated<'a>(fonts: &'a Bundled, text: &str) -> (TextLayout, Vec<TextSpan<'a>>) {
+        let spans = vec![TextSpan {
+            decoration: TextDecoration::new(0b111, 0, 0.0),
+            ..fonts.span(0..text.len())
+        }];
+        (layout_text(text, &spans, TextAlign::Start, &TextLayoutOptions::test(0.0)), spans)
+    }
+
+    fn decorations_of(layout: &TextLayout, kind: DecorationKind) -> Vec<&DecorationLine> {
+        layout.decorations.iter().filter(|line| line.kind == kind).collect()
+    }
+
+    #[test]
+    fn decorations_sit_at_the_font_offsets() {
+        let fonts = bundled(32.0);
+        let (layout, spans) = decorated(&fonts, "aaa");
+        let chain = &spans[0].chain;
+        let baseline = layout.lines[0].baseline;
+        let width = layout.lines[0].line.width;
+
+        let underline = decorations_of(&layout, DecorationKind::Underline);
+        assert_eq!(underline.len(), 1);
+        assert_eq!((underline[0].x0, underline[0].x1), (0.0, width));
+        assert_eq!(underline[0].thickness, chain.underline.thickness);
+        assert_eq!(underline[0].y, baseline - chain.underline.offset + chain.underline.thickness * 0.5);
+        assert!(underline[0].y > baseline);
+
+        let line_through = decorations_of(&layout, DecorationKind::LineThrough);
+        assert_eq!(line_through.len(), 1);
+        assert_eq!(line_through[0].thickness, chain.strikeout.thickness);
+        assert_eq!(
+            line_through[0].y,
+            baseline - chain.strikeout.offset + chain.strikeout.thickness * 0.5
+        );
+        assert!(line_through[0].y < baseline);
+
+        // The overline's top edge is the top of the line box.
+        let overline = decorations_of(&layout, DecorationKind::Overline);
+        assert_eq!(overline.len(), 1);
+        assert_eq!(overline[0].y - overline[0].thickness * 0.5, baseline - chain.ascent);
+        assert!(overline[0].y < line_through[0].y);
+    }
+
+    #[test]
+    fn underlines_skip_descenders() {
+        let fonts = bundled(32.0);
+        let (layout, _) = decorated(&fonts, "aga");
+        let line = &layout.lines[0].line;
+        let a = line.glyphs[0].advance;
+        let g = line.glyphs[1].advance;
+
+        // The underline breaks around the descender of the "g" and stays whole elsewhere.
+        let underline = decorations_of(&layout, DecorationKind::Underline);
+        assert_eq!(underline.len(), 2);
+        assert_eq!(underline[0].x0, 0.0);
+        assert!(underline[0].x1 > 0.0 && underline[0].x1 < a + g * 0.5);
+        assert!(underline[1].x0 > a + g * 0.5 && underline[1].x0 < a + g);
+        assert_eq!(underline[1].x1, line.width);
+        assert_eq!(decorations_of(&layout, DecorationKind::LineThrough).len(), 1);
+        assert_eq!(decorations_of(&layout, DecorationKind::Overline).len(), 1);
+    }
+
+    #[test]
+    fn default_emoji_presentation_prefers_color() {
+        assert_eq!(prefers_color("\u{1F600}"), Some(true));
+        assert_eq!(prefers_color("\u{1F600}\u{FE0E}"), Some(false));
+    }
+
+    #[test]
+    fn flags_and_keycaps_prefer_color() {
+        assert_eq!(prefers_color("\u{1F1EF}\u{1F1F5}"), Some(true));
+        assert_eq!(prefers_color("1\u{20E3}"), Some(true));
+        assert_eq!(prefers_color("1\u{FE0F}\u{20E3}"), Some(true));
+    }
+
+    #[test]
+    fn text_takes_any_face() {
+        assert_eq!(prefers_color("a"), None);
+        assert_eq!(prefers_color("\u{263A}"), None);
+        assert_eq!(prefers_color("\u{263A}\u{FE0F}"), Some(true));
+    }
+}
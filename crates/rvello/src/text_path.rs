@@ -0,0 +1,321 @@
+use vello::kurbo::{
+    Affine, BezPath, CubicBez, ParamCurve, ParamCurveArclen, ParamCurveDeriv, PathEl, Point, Rect, Vec2,
+};
+
+use crate::text::{GlyphRun, TextAlign, TextLayout};
+
+/// Accuracy, in pixels, of arc length measurements along a path.
+const ARCLEN_ACCURACY: f64 = 0.01;
+
+/// Largest difference between unit tangents, squared, that still counts as one direction.
+const TANGENT_TOLERANCE: f64 = 1e-12;
+
+/// Side of a path text is set on, mirroring SVG `side`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum PathSide {
+    Left,
+    /// Runs the path backwards, so text sits on its other side.
+    Right,
+}
+
+impl PathSide {
+    pub(crate) fn from_u8(value: u8) -> Self {
+        match value {
+            1 => PathSide::Right,
+            _ => PathSide::Left,
+        }
+    }
+}
+
+/// A path measured by arc length, along which text is set.
+pub(crate) struct TextPath {
+    /// Segments of every subpath with the arc length at which each starts. Moves between
+    /// subpaths add no length.
+    segments: Vec<(CubicBez, f64)>,
+    length: f64,
+    /// Whether the path is a single closed subpath, around which text wraps.
+    closed: bool,
+}
+
+impl TextPath {
+    pub(crate) fn new(path: &BezPath, side: PathSide) -> Self {
+        let path = match side {
+            PathSide::Left => path.clone(),
+            PathSide::Right => path.reverse_subpaths(),
+        };
+        let elements = path.elements();
+        let subpaths = elements
+            .iter()
+            .filter(|el| matches!(el, PathEl::MoveTo(_)))
+            .count();
+        let closed = subpaths == 1 && matches!(elements.last(), Some(PathEl::ClosePath));
+
+        let mut length = 0.0;
+        let mut segments = Vec::new();
+        for segment in path.segments() {
+            let cubic = segment.to_cubic();
+            let arclen = cubic.arclen(ARCLEN_ACCURACY);
+            // Zero-length segments, such as the close of an already closed outline, have no
+            // tangent to set glyphs on.
+            if arclen > 0.0 {
+                segments.push((cubic, length));
+                length += arclen;
+            }
+        }
+        TextPath {
+            segments,
+            length,
+            closed,
+        }
+    }
+
+    /// Point and unit tangent at arc length `distance`, wrapping around closed paths. `None`
+    /// past either end of an open path.
+    fn sample(&self, distance: f64) -> Option<(Point, Vec2)> {
+        if self.length <= 0.0 {
+            return None;
+        }
+        let distance = if self.closed {
+            distance.rem_euclid(self.length)
+        } else if (0.0..=self.length).contains(&distance) {
+            distance
+        } else {
+            return None;
+        };
+        let index = self
+            .segments
+            .partition_point(|(_, start)| *start <= distance)
+            .saturating_sub(1);
+        let (cubic, start) = &self.segments[index];
+        let t = cubic.inv_arclen(distance - start, ARCLEN_ACCURACY);
+        let mut tangent = cubic.deriv().eval(t).to_vec2();
+        if tangent.hypot2() < 1e-12 {
+            // Control points that coincide with an end point leave no derivative there.
+            tangent = cubic.eval((t + 1e-3).min(1.0)) - cubic.eval((t - 1e-3).max(0.0));
+        }
+        Some((cubic.eval(t), tangent.normalize()))
+    }
+}
+
+/// Sets the glyphs of `layout` along `path`, as SVG `textPath` does. Each glyph is centred on
+/// the point at the arc length of its middle and turned to the tangent there, and lines after
+/// the first follow the path at their distance below the first baseline. `align` anchors
+/// every line at `start_offset` along the path; glyphs whose middle falls past the ends of an
+/// open path are dropped.
+///
+/// Returns the runs, each turning its glyphs with its transform, and the bounds of their line
+/// boxes. Consecutive glyphs of a span and face that share a tangent, as along a straight
+/// stretch of the path, are drawn as one run.
+pub(crate) fn glyph_runs_on_path(
+    layout: &TextLayout,
+    path: &TextPath,
+    start_offset: f32,
+    align: TextAlign,
+) -> (Vec<GlyphRun>, Rect) {
+    let mut runs = Vec::new();
+    let mut bounds: Option<Rect> = None;
+    let Some(first) = layout.lines.first() else {
+        return (runs, Rect::ZERO);
+    };
+    for placed in &layout.lines {
+        let line = &placed.line;
+        let anchor = match (align, line.rtl) {
+            (TextAlign::Start | TextAlign::Justify, false) | (TextAlign::End, true) => 0.0,
+            (TextAlign::Center, _) => 0.5,
+            (TextAlign::End, false) | (TextAlign::Start | TextAlign::Justify, true) => 1.0,
+        };
+        let shift = placed.baseline - first.baseline;
+        let mut pen = start_offset - line.width * anchor;
+        for shaped in &line.glyphs {
+            let middle = pen + shaped.advance * 0.5;
+            pen += shaped.advance;
+            if !shaped.visible {
+                continue;
+            }
+            let Some((point, tangent)) = path.sample(middle as f64) else {
+                continue;
+            };
+            // Glyph x runs along the tangent and y, down, along its clockwise normal.
+            let rotation = Affine::new([tangent.x, tangent.y, -tangent.y, tangent.x, 0.0, 0.0]);
+            let offset = Vec2::new(-shaped.advance as f64 * 0.5, shift as f64);
+            let transform = rotation.then_translate(point.to_vec2()) * Affine::translate(offset);
+            let line_box = Rect::new(
+                0.0,
+                -line.ascent as f64,
+                shaped.advance as f64,
+                -line.descent as f64,
+            );
+            let glyph_bounds = transform.transform_rect_bbox(line_box);
+            bounds = Some(bounds.map_or(glyph_bounds, |bounds| bounds.union(glyph_bounds)));
+
+            // Glyphs are positioned in the frame of their run's rotation.
+            let origin = rotation.inverse() * point + offset;
+            let glyph = vello::Glyph {
+                id: shaped.id,
+                x: origin.x as f32 + shaped.x_offset,
+                y: origin.y as f32 - shaped.y_offset,
+            };
+            match runs.last_mut() {
+                Some(run)
+                    if run.span == shaped.span
+                        && run.face == shaped.face
+                        && run.color == shaped.color
+                        && turns_to(run.transform, tangent) =>
+                {
+                    run.glyphs.push(glyph)
+                }
+                _ => runs.push(GlyphRun {
+                    span: shaped.span,
+                    face: shaped.face,
+                    color: shaped.color,
+                    upright: false,
+                    transform: rotation,
+                    glyphs: vec![glyph],
+                }),
+            }
+        }
+    }
+    (runs, bounds.unwrap_or(Rect::ZERO))
+}
+
+/// Whether the rotation `transform` turns glyph x onto `tangent`.
+fn turns_to(transform: Affine, tangent: Vec2) -> bool {
+    let [x, y, ..] = transform.as_coeffs();
+    (Vec2::new(x, y) - tangent).hypot2() < TANGENT_TOLERANCE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::{LineLayout, PlacedLine, ShapedGlyph};
+
+    fn polyline(points: &[(f64, f64)], close: bool) -> BezPath {
+        let mut path = BezPath::new();
+        path.move_to(points[0]);
+        for &point in &points[1..] {
+            path.line_to(point);
+        }
+        if close {
+            path.close_path();
+        }
+        path
+    }
+
+    fn assert_sample(path: &TextPath, distance: f64, point: (f64, f64), tangent: (f64, f64)) {
+        let (actual_point, actual_tangent) = path.sample(distance).expect("distance is on the path");
+        assert!(
+            (actual_point - Point::from(point)).hypot() <= ARCLEN_ACCURACY,
+            "{actual_point:?} at {distance}"
+        );
+        assert!(
+            (actual_tangent - Vec2::from(tangent)).hypot() < 1e-6,
+            "{actual_tangent:?} at {distance}"
+        );
+    }
+
+    /// A line of `count` glyphs of span 0, each 10 wide, with its baseline at the origin.
+    fn layout(count: usize) -> TextLayout {
+        let glyphs: Vec<ShapedGlyph> = (0..count)
+            .map(|index| ShapedGlyph {
+                cluster: index,
+                ..ShapedGlyph::test(index as u32 + 1, 10.0)
+            })
+            .collect();
+        let line = LineLayout {
+            text: "x".repeat(count),
+            range: 0..count,
+            glyphs,
+            width: 10.0 * count as f32,
+            rtl: false,
+            justifiable: false,
+            ascent: 8.0,
+            descent: -2.0,
+            height: 10.0,
+        };
+        TextLayout {
+            lines: vec![PlacedLine {
+                line,
+                x: 0.0,
+                baseline: 0.0,
+            }],
+            decorations: Vec::new(),
+            vertical: false,
+        }
+    }
+
+    /// Page position of the origin of every glyph in `runs`.
+    fn origins(runs: &[GlyphRun]) -> Vec<Point> {
+        runs.iter()
+            .flat_map(|run| {
+                run.glyphs
+                    .iter()
+                    .map(|glyph| run.transform * Point::new(glyph.x as f64, glyph.y as f64))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn samples_open_paths_by_arc_length() {
+        let path = TextPath::new(
+            &polyline(&[(0.0, 0.0), (30.0, 0.0), (30.0, 40.0)], false),
+            PathSide::Left,
+        );
+        assert_sample(&path, 0.0, (0.0, 0.0), (1.0, 0.0));
+        assert_sample(&path, 20.0, (20.0, 0.0), (1.0, 0.0));
+        assert_sample(&path, 50.0, (30.0, 20.0), (0.0, 1.0));
+        assert_sample(&path, 70.0, (30.0, 40.0), (0.0, 1.0));
+        assert!(path.sample(-1.0).is_none());
+        assert!(path.sample(71.0).is_none());
+    }
+
+    #[test]
+    fn samples_wrap_around_closed_paths() {
+        let square = polyline(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)], true);
+        let path = TextPath::new(&square, PathSide::Left);
+        assert_sample(&path, 45.0, (5.0, 0.0), (1.0, 0.0));
+        assert_sample(&path, -5.0, (0.0, 5.0), (0.0, -1.0));
+    }
+
+    #[test]
+    fn right_side_runs_the_path_backwards() {
+        let path = TextPath::new(&polyline(&[(0.0, 0.0), (30.0, 0.0)], false), PathSide::Right);
+        assert_sample(&path, 10.0, (20.0, 0.0), (-1.0, 0.0));
+    }
+
+    #[test]
+    fn glyphs_past_the_end_of_an_open_path_are_dropped() {
+        // Glyph middles fall at 5, 15, 25, 35 and 45 along a path 30 long.
+        let path = TextPath::new(&polyline(&[(0.0, 0.0), (30.0, 0.0)], false), PathSide::Left);
+        let (runs, bounds) = glyph_runs_on_path(&layout(5), &path, 0.0, TextAlign::Start);
+        let ids: Vec<u32> = runs
+            .iter()
+            .flat_map(|run| run.glyphs.iter().map(|glyph| glyph.id))
+            .collect();
+        assert_eq!(ids, [1, 2, 3]);
+        let expected = Rect::new(0.0, -8.0, 30.0, 2.0);
+        assert!((bounds.min_x() - expected.min_x()).abs() <= ARCLEN_ACCURACY);
+        assert!((bounds.max_x() - expected.max_x()).abs() <= ARCLEN_ACCURACY);
+        assert_eq!(
+            (bounds.min_y(), bounds.max_y()),
+            (expected.min_y(), expected.max_y())
+        );
+    }
+
+    #[test]
+    fn glyphs_sharing_a_tangent_form_one_run() {
+        // Two glyphs on each leg of a right-angled corner.
+        let corner = polyline(&[(0.0, 0.0), (20.0, 0.0), (20.0, 20.0)], false);
+        let path = TextPath::new(&corner, PathSide::Left);
+        let (runs, _) = glyph_runs_on_path(&layout(4), &path, 0.0, TextAlign::Start);
+        let lengths: Vec<usize> = runs.iter().map(|run| run.glyphs.len()).collect();
+        assert_eq!(lengths, [2, 2]);
+
+        let expected = [(0.0, 0.0), (10.0, 0.0), (20.0, 0.0), (20.0, 10.0)].map(Point::from);
+        for (origin, expected) in origins(&runs).into_iter().zip(expected) {
+            assert!(
+                (origin - expected).hypot() <= ARCLEN_ACCURACY,
+                "{origin:?} != {expected:?}"
+            );
+        }
+    }
+}
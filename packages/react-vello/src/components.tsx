@@ -16,6 +16,7 @@ import type {
   PathProps,
   RadialGradientProps,
   RectProps,
+  SweepGradientProps,
   TextProps,
 } from "./types";
 
@@ -51,6 +52,10 @@ export const RadialGradient = createHostComponent<
   "RadialGradient",
   RadialGradientProps
 >("RadialGradient");
+export const SweepGradient = createHostComponent<
+  "SweepGradient",
+  SweepGradientProps
+>("SweepGradient");
 export const Mask = createHostComponent<"Mask", MaskProps>("Mask");
 export const ClipPath = createHostComponent<"ClipPath", ClipPathProps>(
   "ClipPath"
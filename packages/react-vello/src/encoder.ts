@@ -1,31 +1,219 @@
-import { type NormalizedRgba, paintToRgba } from "./color";
-import { resolveCornerRadius } from "./geometry";
+import { type NormalizedRgba, normalizeColor, paintToRgba } from "./color";
+import {
+  type CornerRadii,
+  hasCornerRadii,
+  resolveCornerRadii,
+} from "./geometry";
+import { type ImageSource, imageIdFor } from "./imageSources";
 import { IDENTITY_MATRIX, multiplyTransforms } from "./mat3";
 import {
+  resolveImageOrigin,
+  resolveImageSize,
   resolveNodeTransform,
   resolveRectOrigin,
   resolveRectSize,
   resolveTextOrigin,
 } from "./nodeProps";
 import type { CanvasContainer, SceneNode } from "./runtime";
-import type { CanvasProps, Mat3, Paint } from "./types";
+import type {
+  CanvasProps,
+  GroupProps,
+  ImageProps,
+  LinearGradientProps,
+  Mat3,
+  Paint,
+  PathProps,
+  RadialGradientProps,
+  Stroke,
+  SweepGradientProps,
+  TextDecoration,
+  TextFont,
+  TextProps,
+} from "./types";
 
 const OpCode = {
   BeginFrame: 1,
   Rect: 2,
   Path: 3,
   Text: 4,
+  PushLayer: 5,
+  PopLayer: 6,
+  PushClip: 7,
+  PushMask: 8,
+  Image: 9,
+  RichText: 10,
+  TextPath: 11,
   EndFrame: 255,
 } as const;
 
+const ClipShape = {
+  None: 0,
+  Rect: 1,
+  RoundedRect: 2,
+  Path: 3,
+} as const;
+
+const ImageFit: Record<NonNullable<ImageProps["fit"]>, number> = {
+  stretch: 0,
+  contain: 1,
+  cover: 2,
+  none: 3,
+};
+
+const PathEncoding = {
+  Svg: 0,
+  Binary: 1,
+} as const;
+
+const TextAlign: Record<NonNullable<TextProps["align"]>, number> = {
+  start: 0,
+  center: 1,
+  end: 2,
+  justify: 3,
+};
+
+const DecorationStyle: Record<
+  NonNullable<TextDecoration["style"]>,
+  number
+> = {
+  solid: 0,
+  dashed: 1,
+  wavy: 2,
+};
+
+const TextDirection: Record<NonNullable<TextProps["direction"]>, number> = {
+  auto: 0,
+  ltr: 1,
+  rtl: 2,
+};
+
+const WhiteSpace: Record<NonNullable<TextProps["whiteSpace"]>, number> = {
+  normal: 0,
+  "pre-wrap": 1,
+  pre: 2,
+};
+
+const TextOverflow: Record<
+  NonNullable<TextProps["textOverflow"]>,
+  number
+> = {
+  visible: 0,
+  clip: 1,
+  ellipsis: 2,
+};
+
+const TextOrientation: Record<
+  NonNullable<TextProps["textOrientation"]>,
+  number
+> = {
+  mixed: 0,
+  upright: 1,
+  sideways: 2,
+};
+
+const FontStyle: Record<NonNullable<TextFont["style"]>, number> = {
+  normal: 0,
+  italic: 1,
+  oblique: 2,
+};
+
+const PaintKind = {
+  Solid: 0,
+  Linear: 1,
+  Radial: 2,
+  Sweep: 3,
+} as const;
+
+const GradientExtend = {
+  pad: 0,
+  repeat: 1,
+  reflect: 2,
+} as const;
+
+type ResolvedPaint =
+  | { kind: "solid"; color: NormalizedRgba }
+  | { kind: "linear"; node: SceneNode<"LinearGradient"> }
+  | { kind: "radial"; node: SceneNode<"RadialGradient"> }
+  | { kind: "sweep"; node: SceneNode<"SweepGradient"> };
+
+type GradientNode =
+  | SceneNode<"LinearGradient">
+  | SceneNode<"RadialGradient">
+  | SceneNode<"SweepGradient">;
+
+interface ResolvedStroke {
+  stroke: Stroke;
+  paint: ResolvedPaint;
+}
+
+const StrokeJoin: Record<NonNullable<Stroke["join"]>, number> = {
+  miter: 0,
+  bevel: 1,
+  round: 2,
+};
+
+const StrokeCap: Record<NonNullable<Stroke["cap"]>, number> = {
+  butt: 0,
+  square: 1,
+  round: 2,
+};
+
+const BlackPaint: ResolvedPaint = {
+  kind: "solid",
+  color: { r: 0, g: 0, b: 0, a: 1 },
+};
+
+const BlendMix: Record<NonNullable<GroupProps["blendMode"]>, number> = {
+  normal: 0,
+  multiply: 1,
+  screen: 2,
+  overlay: 3,
+  darken: 4,
+  lighten: 5,
+  "color-dodge": 6,
+  "color-burn": 7,
+  "hard-light": 8,
+  "soft-light": 9,
+  difference: 10,
+  exclusion: 11,
+  hue: 12,
+  saturation: 13,
+  color: 14,
+  luminosity: 15,
+};
+
+const BlendCompose: Record<NonNullable<GroupProps["composite"]>, number> = {
+  clear: 0,
+  copy: 1,
+  destination: 2,
+  "source-over": 3,
+  "destination-over": 4,
+  "source-in": 5,
+  "destination-in": 6,
+  "source-out": 7,
+  "destination-out": 8,
+  "source-atop": 9,
+  "destination-atop": 10,
+  xor: 11,
+  lighter: 12,
+  "plus-lighter": 13,
+};
+
 const textEncoder = new TextEncoder();
 
 interface EncoderState {
   transform: Mat3;
-  opacity: number;
+  definitions: Map<string, SceneNode>;
+  images: Map<number, ImageSource>;
+}
+
+export interface EncodedFrame {
+  ops: Uint8Array;
+  /** Sources of the images the frame draws, by the id its opcodes use. */
+  images: Map<number, ImageSource>;
 }
 
-export function encodeFrame(container: CanvasContainer): Uint8Array | null {
+export function encodeFrame(container: CanvasContainer): EncodedFrame | null {
   const root = container.root;
   if (!root || root.type !== "Canvas") {
     return null;
@@ -64,14 +252,15 @@ export function encodeFrame(container: CanvasContainer): Uint8Array | null {
 
   const initialState: EncoderState = {
     transform: dpr === 1 ? IDENTITY_MATRIX : ([dpr, 0, 0, dpr, 0, 0] as Mat3),
-    opacity: 1,
+    definitions: collectDefinitions(canvasNode, new Map()),
+    images: new Map(),
   };
   for (const child of canvasNode.children) {
     encodeNode(child, writer, initialState);
   }
 
   writer.writeUint8(OpCode.EndFrame);
-  return writer.take();
+  return { ops: writer.take(), images: initialState.images };
 }
 
 function encodeNode(
@@ -84,20 +273,9 @@ function encodeNode(
   }
 
   switch (node.type) {
-    case "Group": {
-      const groupNode = node as SceneNode<"Group">;
-      const nextState = {
-        transform: multiplyTransforms(
-          state.transform,
-          resolveNodeTransform(groupNode.props, groupNode.dragOffset)
-        ),
-        opacity: state.opacity * (groupNode.props.opacity ?? 1),
-      };
-      for (const child of groupNode.children) {
-        encodeNode(child, writer, nextState);
-      }
+    case "Group":
+      encodeGroup(node as SceneNode<"Group">, writer, state);
       break;
-    }
     case "Rect":
       encodeRect(node as SceneNode<"Rect">, writer, state);
       break;
@@ -107,20 +285,165 @@ function encodeNode(
     case "Text":
       encodeText(node as SceneNode<"Text">, writer, state);
       break;
+    case "Image":
+      encodeImage(node as SceneNode<"Image">, writer, state);
+      break;
     default:
       // Other primitives will be added as the renderer matures.
       break;
   }
 }
 
+function encodeGroup(
+  node: SceneNode<"Group">,
+  writer: BinaryWriter,
+  state: EncoderState
+): void {
+  const props = node.props;
+  const nextState: EncoderState = {
+    ...state,
+    transform: multiplyTransforms(
+      state.transform,
+      resolveNodeTransform(props, node.dragOffset)
+    ),
+  };
+
+  const clipNode = props.clipPath
+    ? state.definitions.get(props.clipPath)
+    : undefined;
+  const clipped =
+    clipNode?.type === "ClipPath" &&
+    encodeClip(clipNode as SceneNode<"ClipPath">, writer, nextState);
+
+  const maskNode = props.mask ? state.definitions.get(props.mask) : undefined;
+  const mask =
+    maskNode?.type === "Mask" ? (maskNode as SceneNode<"Mask">) : undefined;
+
+  // Group opacity is applied once to the composited subtree so that
+  // overlapping children don't double-blend.
+  const opacity = props.opacity ?? 1;
+  const mix = BlendMix[props.blendMode ?? "normal"];
+  const compose = BlendCompose[props.composite ?? "source-over"];
+  const needsLayer =
+    mask !== undefined ||
+    opacity < 1 ||
+    mix !== BlendMix.normal ||
+    compose !== BlendCompose["source-over"];
+  if (needsLayer) {
+    writer.writeUint8(OpCode.PushLayer);
+    writer.writeFloat32(Math.max(0, opacity));
+    writer.writeUint8(mix);
+    writer.writeUint8(compose);
+    writeMat3(writer, IDENTITY_MATRIX);
+    writer.writeUint8(ClipShape.None);
+  }
+  for (const child of node.children) {
+    encodeNode(child, writer, nextState);
+  }
+  if (mask) {
+    encodeMask(mask, writer, nextState);
+  }
+  if (needsLayer) {
+    writer.writeUint8(OpCode.PopLayer);
+  }
+  if (clipped) {
+    writer.writeUint8(OpCode.PopLayer);
+  }
+}
+
+// Mask content is drawn after the group's children, inside the group layer.
+function encodeMask(
+  node: SceneNode<"Mask">,
+  writer: BinaryWriter,
+  state: EncoderState
+): void {
+  const maskState: EncoderState = {
+    ...state,
+    transform: multiplyTransforms(
+      state.transform,
+      resolveNodeTransform(node.props, node.dragOffset)
+    ),
+  };
+
+  writer.writeUint8(OpCode.PushMask);
+  writer.writeUint8(node.props.mode === "luminance" ? 1 : 0);
+  writer.writeFloat32(Math.max(0, node.props.opacity ?? 1));
+  writeMat3(writer, IDENTITY_MATRIX);
+  writer.writeUint8(ClipShape.None);
+  for (const child of node.children) {
+    encodeNode(child, writer, maskState);
+  }
+  writer.writeUint8(OpCode.PopLayer);
+}
+
+// Clips to the first visible Rect or Path inside the ClipPath element.
+function encodeClip(
+  node: SceneNode<"ClipPath">,
+  writer: BinaryWriter,
+  state: EncoderState
+): boolean {
+  const clipTransform = multiplyTransforms(
+    state.transform,
+    resolveNodeTransform(node.props, node.dragOffset)
+  );
+
+  for (const child of node.children) {
+    if ((child.props as { visible?: boolean }).visible === false) {
+      continue;
+    }
+    if (child.type === "Rect") {
+      const rectNode = child as SceneNode<"Rect">;
+      const origin = resolveRectOrigin(rectNode.props);
+      const size = resolveRectSize(rectNode.props);
+      const radii = resolveCornerRadii(rectNode.props.radius);
+      const rounded = hasCornerRadii(radii);
+      writer.writeUint8(OpCode.PushClip);
+      writeMat3(
+        writer,
+        multiplyTransforms(
+          clipTransform,
+          resolveNodeTransform(rectNode.props, rectNode.dragOffset)
+        )
+      );
+      writer.writeUint8(rounded ? ClipShape.RoundedRect : ClipShape.Rect);
+      writer.writeFloat32(origin[0]);
+      writer.writeFloat32(origin[1]);
+      writer.writeFloat32(size[0]);
+      writer.writeFloat32(size[1]);
+      if (rounded) {
+        writeCornerRadii(writer, radii);
+      }
+      return true;
+    }
+    if (child.type === "Path") {
+      const pathNode = child as SceneNode<"Path">;
+      writer.writeUint8(OpCode.PushClip);
+      writeMat3(
+        writer,
+        multiplyTransforms(
+          clipTransform,
+          resolveNodeTransform(pathNode.props, pathNode.dragOffset)
+        )
+      );
+      writer.writeUint8(ClipShape.Path);
+      writer.writeUint8(pathNode.props.fillRule === "evenodd" ? 1 : 0);
+      writePathData(writer, pathNode.props.d);
+      return true;
+    }
+  }
+
+  return false;
+}
+
 function encodeRect(
   node: SceneNode<"Rect">,
   writer: BinaryWriter,
   state: EncoderState
 ): void {
   const props = node.props;
-  const fill = resolveFill(props.fill);
-  if (!fill) {
+  const fill = resolvePaint(props.fill, state);
+  const stroke = resolveStroke(props.stroke, state);
+  if (!(fill || stroke)) {
     return;
   }
 
@@ -128,10 +451,10 @@ function encodeRect(
     state.transform,
     resolveNodeTransform(props, node.dragOffset)
   );
-  const opacity = state.opacity * (props.opacity ?? 1);
+  const opacity = props.opacity ?? 1;
   const origin = resolveRectOrigin(props);
   const size = resolveRectSize(props);
-  const radius = resolveCornerRadius(props.radius, size[0], size[1]);
+  const radii = resolveCornerRadii(props.radius);
 
   writer.writeUint8(OpCode.Rect);
   writer.writeFloat32(opacity);
@@ -140,11 +463,16 @@ function encodeRect(
   writer.writeFloat32(origin[1]);
   writer.writeFloat32(size[0]);
   writer.writeFloat32(size[1]);
-  writer.writeFloat32(radius);
-  writer.writeFloat32(fill.r);
-  writer.writeFloat32(fill.g);
-  writer.writeFloat32(fill.b);
-  writer.writeFloat32(fill.a);
+  writeCornerRadii(writer, radii);
+  writeOptionalPaint(writer, fill);
+  writeOptionalStroke(writer, stroke);
+}
+
+function writeCornerRadii(writer: BinaryWriter, radii: CornerRadii): void {
+  for (const [x, y] of radii) {
+    writer.writeFloat32(x);
+    writer.writeFloat32(y);
+  }
 }
 
 function encodePath(
@@ -153,8 +481,9 @@ function encodePath(
   state: EncoderState
 ): void {
   const props = node.props;
-  const fill = resolveFill(props.fill);
-  if (!(fill || props.stroke)) {
+  const fill = resolvePaint(props.fill, state);
+  const stroke = resolveStroke(props.stroke, state);
+  if (!(fill || stroke)) {
     return;
   }
 
@@ -162,48 +491,65 @@ function encodePath(
     state.transform,
     resolveNodeTransform(props, node.dragOffset)
   );
-  const opacity = state.opacity * (props.opacity ?? 1);
-  const pathData = props.d || "";
+  const opacity = props.opacity ?? 1;
   const fillRule = props.fillRule === "evenodd" ? 1 : 0;
 
-  const pathBytes = textEncoder.encode(pathData);
-
   writer.writeUint8(OpCode.Path);
   writer.writeFloat32(opacity);
   writeMat3(writer, transform);
   writer.writeUint8(fillRule);
 
-  // Write fill
-  if (fill) {
-    writer.writeUint8(1); // has fill
-    writer.writeFloat32(fill.r);
-    writer.writeFloat32(fill.g);
-    writer.writeFloat32(fill.b);
-    writer.writeFloat32(fill.a);
-  } else {
-    writer.writeUint8(0); // no fill
-  }
-
-  // Write stroke
-  if (props.stroke) {
-    const strokeColor = resolveFill(props.stroke.paint);
-    if (strokeColor) {
-      writer.writeUint8(1); // has stroke
-      writer.writeFloat32(props.stroke.width);
-      writer.writeFloat32(strokeColor.r);
-      writer.writeFloat32(strokeColor.g);
-      writer.writeFloat32(strokeColor.b);
-      writer.writeFloat32(strokeColor.a);
-    } else {
-      writer.writeUint8(0); // no stroke
-    }
-  } else {
-    writer.writeUint8(0); // no stroke
+  writeOptionalPaint(writer, fill);
+  writeOptionalStroke(writer, stroke);
+  writePathData(writer, props.d);
+}
+
+function writePathData(writer: BinaryWriter, d: PathProps["d"]): void {
+  if (typeof d === "string" || !d) {
+    const pathBytes = textEncoder.encode(d || "");
+    writer.writeUint8(PathEncoding.Svg);
+    writer.writeUint32(pathBytes.length);
+    writer.writeBytes(pathBytes);
+    return;
+  }
+
+  writer.writeUint8(PathEncoding.Binary);
+  writer.writeUint32(d.verbs.length);
+  writer.writeBytes(d.verbs);
+  const pointCount = Math.floor(d.points.length / 2);
+  writer.writeUint32(pointCount);
+  writer.writeFloat32Array(d.points.subarray(0, pointCount * 2));
+}
+
+function encodeImage(
+  node: SceneNode<"Image">,
+  writer: BinaryWriter,
+  state: EncoderState
+): void {
+  const props = node.props;
+  if (!props.source) {
+    return;
   }
 
-  // Write path data string
-  writer.writeUint32(pathBytes.length);
-  writer.writeBytes(pathBytes);
+  const transform = multiplyTransforms(
+    state.transform,
+    resolveNodeTransform(props, node.dragOffset)
+  );
+  const opacity = props.opacity ?? 1;
+  const origin = resolveImageOrigin(props);
+  const size = resolveImageSize(props);
+  const id = imageIdFor(props.source);
+  state.images.set(id, props.source);
+
+  writer.writeUint8(OpCode.Image);
+  writer.writeFloat32(opacity);
+  writeMat3(writer, transform);
+  writer.writeFloat32(origin[0]);
+  writer.writeFloat32(origin[1]);
+  writer.writeFloat32(size[0]);
+  writer.writeFloat32(size[1]);
+  writer.writeUint8(ImageFit[props.fit ?? "stretch"]);
+  writer.writeUint32(id);
 }
 
 function encodeText(
@@ -212,44 +558,220 @@ function encodeText(
   state: EncoderState
 ): void {
   const props = node.props;
-  const text = props.text ?? "";
-  if (!text) {
+  const content = props.spans
+    ? props.spans.map((span) => span.text).join("")
+    : (props.text ?? "");
+  if (!content) {
     return;
   }
 
-  const fill = resolveFill(props.fill) ?? { r: 0, g: 0, b: 0, a: 1 };
   const transform = multiplyTransforms(
     state.transform,
     resolveNodeTransform(props, node.dragOffset)
   );
-  const opacity = state.opacity * (props.opacity ?? 1);
+  if (props.path) {
+    // The path header is followed by the Text or RichText opcode it sets.
+    writer.writeUint8(OpCode.TextPath);
+    writePathData(writer, props.path.d);
+    writer.writeFloat32(props.path.startOffset ?? 0);
+    writer.writeUint8(props.path.side === "right" ? 1 : 0);
+  }
+  writeTextOp(writer, props, transform, (paint) => resolvePaint(paint, state));
+}
+
+/**
+ * Encodes the layout of a `Text` element as a single opcode for the renderer's
+ * `measure_text`, `hit_test_text`, `text_caret` and `text_selection`, which lay it
+ * out with the same code path that draws it.
+ */
+export function encodeTextMeasurement(props: TextProps): Uint8Array {
+  const writer = new BinaryWriter();
+  writeTextOp(writer, props, IDENTITY_MATRIX, () => null);
+  return writer.take();
+}
+
+// Elements with spans are encoded as RichText; the rest as a single-style Text.
+function writeTextOp(
+  writer: BinaryWriter,
+  props: TextProps,
+  transform: Mat3,
+  resolveTextPaint: (paint: Paint | undefined) => ResolvedPaint | null
+): void {
   const origin = resolveTextOrigin(props);
-  const fontSize = props.font?.size ?? 16;
-  const lineHeight = props.font?.lineHeight ?? 0;
-  const maxWidth = props.maxWidth ?? 0;
-  let align = 0;
-  if (props.align === "center") {
-    align = 1;
-  } else if (props.align === "end") {
-    align = 2;
+  const align = TextAlign[props.align ?? "start"];
+
+  if (props.spans) {
+    writer.writeUint8(OpCode.RichText);
+    writer.writeFloat32(props.opacity ?? 1);
+    writeMat3(writer, transform);
+    writer.writeFloat32(origin[0]);
+    writer.writeFloat32(origin[1]);
+    writer.writeFloat32(props.maxWidth ?? 0);
+    writer.writeUint8(align);
+    writer.writeFloat32(props.wordSpacing ?? 0);
+    writeTextOptions(writer, props);
+    writer.writeUint32(props.spans.length);
+    for (const span of props.spans) {
+      const font = {
+        ...props.font,
+        ...span.font,
+        variations: { ...props.font?.variations, ...span.font?.variations },
+      };
+      writer.writeFloat32(font.size ?? 16);
+      writer.writeFloat32(font.lineHeight ?? 0);
+      writer.writeFloat32(span.letterSpacing ?? props.letterSpacing ?? 0);
+      writeTextFont(writer, font);
+      writeTextPaint(
+        writer,
+        span.fill === undefined ? props.fill : span.fill,
+        span.stroke === undefined ? props.stroke : span.stroke,
+        span.paintOrder ?? props.paintOrder,
+        resolveTextPaint
+      );
+      writeTextDecoration(
+        writer,
+        { ...props.decoration, ...span.decoration },
+        resolveTextPaint
+      );
+      writeString(writer, span.text);
+    }
+    return;
   }
-  const textBytes = textEncoder.encode(text);
 
   writer.writeUint8(OpCode.Text);
-  writer.writeFloat32(opacity);
+  writer.writeFloat32(props.opacity ?? 1);
   writeMat3(writer, transform);
   writer.writeFloat32(origin[0]);
   writer.writeFloat32(origin[1]);
-  writer.writeFloat32(fontSize);
-  writer.writeFloat32(lineHeight);
-  writer.writeFloat32(maxWidth);
+  writer.writeFloat32(props.font?.size ?? 16);
+  writer.writeFloat32(props.font?.lineHeight ?? 0);
+  writer.writeFloat32(props.maxWidth ?? 0);
   writer.writeUint8(align);
-  writer.writeFloat32(fill.r);
-  writer.writeFloat32(fill.g);
-  writer.writeFloat32(fill.b);
-  writer.writeFloat32(fill.a);
-  writer.writeUint32(textBytes.length);
-  writer.writeBytes(textBytes);
+  writer.writeFloat32(props.letterSpacing ?? 0);
+  writer.writeFloat32(props.wordSpacing ?? 0);
+  writeTextOptions(writer, props);
+  writeTextFont(writer, props.font ?? {});
+  writeTextPaint(
+    writer,
+    props.fill,
+    props.stroke,
+    props.paintOrder,
+    resolveTextPaint
+  );
+  writeTextDecoration(writer, props.decoration ?? {}, resolveTextPaint);
+  writeString(writer, props.text ?? "");
+}
+
+// An undefined fill is black; a null fill leaves only the stroke.
+function writeTextPaint(
+  writer: BinaryWriter,
+  fill: Paint | null | undefined,
+  stroke: Stroke | null | undefined,
+  paintOrder: TextProps["paintOrder"],
+  resolveTextPaint: (paint: Paint | undefined) => ResolvedPaint | null
+): void {
+  writeOptionalPaint(
+    writer,
+    fill === null ? null : (resolveTextPaint(fill) ?? BlackPaint)
+  );
+  const strokePaint = stroke ? resolveTextPaint(stroke.paint) : null;
+  writeOptionalStroke(
+    writer,
+    stroke && strokePaint ? { stroke, paint: strokePaint } : null
+  );
+  writer.writeUint8(paintOrder === "stroke" ? 1 : 0);
+}
+
+function writeTextDecoration(
+  writer: BinaryWriter,
+  decoration: TextDecoration,
+  resolveTextPaint: (paint: Paint | undefined) => ResolvedPaint | null
+): void {
+  let lines = 0;
+  if (decoration.underline) {
+    lines |= 1;
+  }
+  if (decoration.lineThrough) {
+    lines |= 2;
+  }
+  if (decoration.overline) {
+    lines |= 4;
+  }
+  writer.writeUint8(lines);
+  writer.writeUint8(DecorationStyle[decoration.style ?? "solid"]);
+  writer.writeFloat32(decoration.thickness ?? 0);
+  writeOptionalPaint(
+    writer,
+    decoration.color ? resolveTextPaint(decoration.color) : null
+  );
+}
+
+function writeTextOptions(writer: BinaryWriter, props: TextProps): void {
+  writer.writeUint8(TextDirection[props.direction ?? "auto"]);
+  writer.writeUint8(WhiteSpace[props.whiteSpace ?? "normal"]);
+  writer.writeUint8(props.overflowWrap === "break-word" ? 1 : 0);
+  writer.writeUint32(Math.max(0, Math.floor(props.maxLines ?? 0)));
+  writer.writeUint8(TextOverflow[props.textOverflow ?? "visible"]);
+  writeString(writer, props.ellipsis ?? "");
+  writer.writeUint8(props.writingMode === "vertical-rl" ? 1 : 0);
+  writer.writeUint8(TextOrientation[props.textOrientation ?? "mixed"]);
+}
+
+function writeTextFont(writer: BinaryWriter, font: Partial<TextFont>): void {
+  writeString(writer, font.family ?? "");
+  writer.writeFloat32(resolveFontWeight(font.weight));
+  writer.writeUint8(FontStyle[font.style ?? "normal"]);
+  writer.writeFloat32(font.width ?? 100);
+  writer.writeFloat32(font.slant ?? 14);
+  const variations = Object.entries(font.variations ?? {});
+  writer.writeUint32(variations.length);
+  for (const [tag, value] of variations) {
+    // OpenType tags are four ASCII bytes, padded with spaces.
+    const padded = tag.padEnd(4, " ");
+    for (let i = 0; i < 4; i++) {
+      writer.writeUint8(padded.charCodeAt(i) & 0xff);
+    }
+    writer.writeFloat32(value);
+  }
+}
+
+function writeString(writer: BinaryWriter, value: string): void {
+  const bytes = textEncoder.encode(value);
+  writer.writeUint32(bytes.length);
+  writer.writeBytes(bytes);
+}
+
+function resolveFontWeight(weight: TextFont["weight"]): number {
+  if (weight === "bold") {
+    return 700;
+  }
+  if (typeof weight === "number" && Number.isFinite(weight)) {
+    return weight;
+  }
+  return 400;
+}
+
+// Definition elements are referenced by id and never drawn directly.
+const DefinitionTypes = new Set<SceneNode["type"]>([
+  "ClipPath",
+  "Mask",
+  "LinearGradient",
+  "RadialGradient",
+  "SweepGradient",
+]);
+
+function collectDefinitions(
+  node: SceneNode,
+  definitions: Map<string, SceneNode>
+): Map<string, SceneNode> {
+  const id = (node.props as { id?: string }).id;
+  if (id && DefinitionTypes.has(node.type)) {
+    definitions.set(id, node);
+  }
+  for (const child of node.children) {
+    collectDefinitions(child, definitions);
+  }
+  return definitions;
 }
 
 function writeMat3(writer: BinaryWriter, mat: Mat3): void {
@@ -258,12 +780,152 @@ function writeMat3(writer: BinaryWriter, mat: Mat3): void {
   }
 }
 
-function resolveFill(paint?: Paint): NormalizedRgba | null {
+function resolvePaint(
+  paint: Paint | undefined,
+  state: EncoderState
+): ResolvedPaint | null {
+  if (paint?.kind === "gradient") {
+    const node = state.definitions.get(paint.ref);
+    if (node?.type === "LinearGradient") {
+      return { kind: "linear", node: node as SceneNode<"LinearGradient"> };
+    }
+    if (node?.type === "RadialGradient") {
+      return { kind: "radial", node: node as SceneNode<"RadialGradient"> };
+    }
+    if (node?.type === "SweepGradient") {
+      return { kind: "sweep", node: node as SceneNode<"SweepGradient"> };
+    }
+    return null;
+  }
   const rgba = paintToRgba(paint);
   if (!rgba) {
     return null;
   }
-  return rgba;
+  return { kind: "solid", color: rgba };
+}
+
+function resolveStroke(
+  stroke: Stroke | undefined,
+  state: EncoderState
+): ResolvedStroke | null {
+  if (!stroke) {
+    return null;
+  }
+  const paint = resolvePaint(stroke.paint, state);
+  if (!paint) {
+    return null;
+  }
+  return { stroke, paint };
+}
+
+function writeOptionalPaint(
+  writer: BinaryWriter,
+  paint: ResolvedPaint | null
+): void {
+  if (!paint) {
+    writer.writeUint8(0); // no paint
+    return;
+  }
+  writer.writeUint8(1);
+  writePaint(writer, paint);
+}
+
+function writeOptionalStroke(
+  writer: BinaryWriter,
+  resolved: ResolvedStroke | null
+): void {
+  if (!resolved) {
+    writer.writeUint8(0); // no stroke
+    return;
+  }
+  const { stroke, paint } = resolved;
+  const dash = stroke.dash ?? [];
+  writer.writeUint8(1);
+  writer.writeFloat32(stroke.width);
+  writer.writeUint8(StrokeJoin[stroke.join ?? "miter"]);
+  writer.writeUint8(StrokeCap[stroke.cap ?? "butt"]);
+  writer.writeFloat32(stroke.miterLimit ?? 10);
+  writer.writeUint32(dash.length);
+  for (const value of dash) {
+    writer.writeFloat32(value);
+  }
+  writer.writeFloat32(stroke.dashOffset ?? 0);
+  writePaint(writer, paint);
+}
+
+function writePaint(writer: BinaryWriter, paint: ResolvedPaint): void {
+  switch (paint.kind) {
+    case "solid":
+      writer.writeUint8(PaintKind.Solid);
+      writeColor(writer, paint.color);
+      break;
+    case "linear": {
+      const props = paint.node.props;
+      writer.writeUint8(PaintKind.Linear);
+      writer.writeFloat32(props.from[0]);
+      writer.writeFloat32(props.from[1]);
+      writer.writeFloat32(props.to[0]);
+      writer.writeFloat32(props.to[1]);
+      writeGradient(writer, paint.node);
+      break;
+    }
+    case "radial": {
+      const props = paint.node.props;
+      const focus = props.focus ?? props.center;
+      writer.writeUint8(PaintKind.Radial);
+      writer.writeFloat32(focus[0]);
+      writer.writeFloat32(focus[1]);
+      writer.writeFloat32(props.focusRadius ?? 0);
+      writer.writeFloat32(props.center[0]);
+      writer.writeFloat32(props.center[1]);
+      writer.writeFloat32(props.radius);
+      writeGradient(writer, paint.node);
+      break;
+    }
+    case "sweep": {
+      const props = paint.node.props;
+      writer.writeUint8(PaintKind.Sweep);
+      writer.writeFloat32(props.center[0]);
+      writer.writeFloat32(props.center[1]);
+      // The renderer takes sweep angles in radians.
+      writer.writeFloat32((props.startAngle ?? 0) * (Math.PI / 180));
+      writer.writeFloat32((props.endAngle ?? 360) * (Math.PI / 180));
+      writeGradient(writer, paint.node);
+      break;
+    }
+  }
+}
+
+// Extend mode, gradient-space transform and color stops shared by all
+// gradient kinds.
+function writeGradient(writer: BinaryWriter, node: GradientNode): void {
+  const props: LinearGradientProps | RadialGradientProps | SweepGradientProps =
+    node.props;
+  const stops: { offset: number; color: NormalizedRgba }[] = [];
+  for (const stop of props.stops) {
+    const color = normalizeColor(stop.color);
+    if (color) {
+      stops.push({ offset: stop.offset, color });
+    }
+  }
+
+  writer.writeUint8(GradientExtend[props.extend ?? "pad"]);
+  writeMat3(
+    writer,
+    resolveNodeTransform(props, node.dragOffset) ?? IDENTITY_MATRIX
+  );
+  writer.writeUint32(stops.length);
+  for (const stop of stops) {
+    writer.writeFloat32(stop.offset);
+    writeColor(writer, stop.color);
+  }
+}
+
+function writeColor(writer: BinaryWriter, color: NormalizedRgba): void {
+  writer.writeFloat32(color.r);
+  writer.writeFloat32(color.g);
+  writer.writeFloat32(color.b);
+  writer.writeFloat32(color.a);
 }
 
 function normalizeBackground(
@@ -306,6 +968,14 @@ class BinaryWriter {
     this.length += 4;
   }
 
+  writeFloat32Array(values: Float32Array): void {
+    this.ensureCapacity(values.length * 4);
+    for (const value of values) {
+      this.view.setFloat32(this.length, value, true);
+      this.length += 4;
+    }
+  }
+
   writeBytes(bytes: Uint8Array): void {
     this.ensureCapacity(bytes.length);
     new Uint8Array(this.buffer, this.length, bytes.length).set(bytes);
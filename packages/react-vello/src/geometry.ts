@@ -1,40 +1,75 @@
 import type { RectProps, Vec2 } from "./types";
 
-export function resolveCornerRadius(
-  radius: RectProps["radius"],
-  width: number,
-  height: number
-): number {
+export type CornerRadii = readonly [Vec2, Vec2, Vec2, Vec2];
+
+/**
+ * Resolves `RectProps.radius` into x/y radii for each corner, clockwise from
+ * the top-left. Overlapping radii are scaled down by the renderer.
+ */
+export function resolveCornerRadii(radius: RectProps["radius"]): CornerRadii {
   if (!radius) {
-    return 0;
+    return [
+      [0, 0],
+      [0, 0],
+      [0, 0],
+      [0, 0],
+    ];
   }
   if (typeof radius === "number") {
-    return clampRadius(radius, width, height);
+    const corner: Vec2 = [radius, radius];
+    return [corner, corner, corner, corner];
   }
 
-  if (Array.isArray(radius)) {
-    const first = radius[0];
-    if (typeof first === "number") {
-      return clampRadius(first, width, height);
-    }
-    if (Array.isArray(first)) {
-      const candidate = first[0];
-      if (typeof candidate === "number") {
-        return clampRadius(candidate, width, height);
-      }
-    }
+  const first = radius[0];
+  if (typeof first === "number") {
+    const corner = normalizeVec2(radius as Vec2);
+    return [corner, corner, corner, corner];
   }
 
-  return 0;
+  const corners = radius as CornerRadii;
+  return [
+    normalizeVec2(corners[0]),
+    normalizeVec2(corners[1]),
+    normalizeVec2(corners[2]),
+    normalizeVec2(corners[3]),
+  ];
+}
+
+export function hasCornerRadii(radii: CornerRadii): boolean {
+  return radii.some(([x, y]) => x > 0 && y > 0);
 }
 
-export function clampRadius(
-  radius: number,
+/**
+ * Drops corners with a zero or invalid radius and scales all radii down
+ * together when adjacent corners would overlap, as CSS `border-radius` does
+ * and as the renderer does for the radii it is sent.
+ */
+export function normalizeCornerRadii(
+  radii: CornerRadii,
   width: number,
   height: number
-): number {
-  const maxRadius = Math.min(Math.abs(width), Math.abs(height)) / 2;
-  return Math.max(0, Math.min(radius, maxRadius));
+): CornerRadii {
+  const valid = ([x, y]: Vec2): Vec2 =>
+    Number.isFinite(x) && Number.isFinite(y) && x > 0 && y > 0
+      ? [x, y]
+      : [0, 0];
+  const topLeft = valid(radii[0]);
+  const topRight = valid(radii[1]);
+  const bottomRight = valid(radii[2]);
+  const bottomLeft = valid(radii[3]);
+  const scales = [
+    Math.abs(width) / (topLeft[0] + topRight[0]),
+    Math.abs(width) / (bottomLeft[0] + bottomRight[0]),
+    Math.abs(height) / (topLeft[1] + bottomLeft[1]),
+    Math.abs(height) / (topRight[1] + bottomRight[1]),
+  ];
+  const scale = Math.min(1, ...scales.filter(Number.isFinite));
+  return [
+    [topLeft[0] * scale, topLeft[1] * scale],
+    [topRight[0] * scale, topRight[1] * scale],
+    [bottomRight[0] * scale, bottomRight[1] * scale],
+    [bottomLeft[0] * scale, bottomLeft[1] * scale],
+  ];
 }
 
 export function normalizeVec2(value: Vec2): readonly [number, number] {
@@ -0,0 +1,104 @@
+import type { ImageProps } from "./types";
+
+export type ImageSource = ImageProps["source"];
+
+// Decoded by the renderer itself; other formats are decoded by the browser.
+const EncodedImageTypes = new Set(["image/png", "image/jpeg"]);
+
+/** Image data read from a source, in the form the renderer registers it. */
+export type ImageUpload =
+  | { kind: "encoded"; bytes: Uint8Array }
+  | { kind: "pixels"; width: number; height: number; pixels: Uint8Array };
+
+const urlIds = new Map<string, number>();
+const objectIds = new WeakMap<ImageBitmap | HTMLImageElement, number>();
+let nextImageId = 1;
+
+/** Returns the renderer id of `source`, shared by every node that draws it. */
+export function imageIdFor(source: ImageSource): number {
+  if (typeof source === "string") {
+    let id = urlIds.get(source);
+    if (id === undefined) {
+      id = nextImageId++;
+      urlIds.set(source, id);
+    }
+    return id;
+  }
+  let id = objectIds.get(source);
+  if (id === undefined) {
+    id = nextImageId++;
+    objectIds.set(source, id);
+  }
+  return id;
+}
+
+/**
+ * Reads the pixels of a source that is already decoded, or returns null when it
+ * still has to be loaded with `loadImageSource`.
+ */
+export function readImageSource(source: ImageSource): ImageUpload | null {
+  if (typeof source === "string") {
+    return null;
+  }
+  // Broken images are complete with no natural size; decoding them again
+  // reports why.
+  if (
+    source instanceof HTMLImageElement &&
+    !(source.complete && source.naturalWidth > 0)
+  ) {
+    return null;
+  }
+  return readPixels(source);
+}
+
+export async function loadImageSource(
+  source: ImageSource
+): Promise<ImageUpload> {
+  if (typeof source !== "string") {
+    if (source instanceof HTMLImageElement) {
+      await source.decode();
+    }
+    return readPixels(source);
+  }
+
+  const response = await fetch(source);
+  if (!response.ok) {
+    throw new Error(`Failed to fetch image ${source}: ${response.status}`);
+  }
+  const blob = await response.blob();
+  if (EncodedImageTypes.has(blob.type)) {
+    return { kind: "encoded", bytes: new Uint8Array(await blob.arrayBuffer()) };
+  }
+  const bitmap = await createImageBitmap(blob);
+  try {
+    return readPixels(bitmap);
+  } finally {
+    bitmap.close();
+  }
+}
+
+function readPixels(source: ImageBitmap | HTMLImageElement): ImageUpload {
+  const width =
+    source instanceof HTMLImageElement ? source.naturalWidth : source.width;
+  const height =
+    source instanceof HTMLImageElement ? source.naturalHeight : source.height;
+  if (width === 0 || height === 0) {
+    throw new Error("Image has no pixels");
+  }
+  const canvas = document.createElement("canvas");
+  canvas.width = width;
+  canvas.height = height;
+  const context = canvas.getContext("2d");
+  if (!context) {
+    throw new Error("Failed to acquire 2D context to read image pixels");
+  }
+  context.drawImage(source, 0, 0);
+  // getImageData returns straight alpha, as the renderer expects.
+  const { data } = context.getImageData(0, 0, width, height);
+  return {
+    kind: "pixels",
+    width,
+    height,
+    pixels: new Uint8Array(data.buffer, data.byteOffset, data.byteLength),
+  };
+}
@@ -5,6 +5,12 @@ import {
   LegacyRoot,
   NoEventPriority,
 } from "react-reconciler/constants";
+import {
+  type ImageSource,
+  type ImageUpload,
+  loadImageSource,
+  readImageSource,
+} from "./imageSources";
 import {
   type CanvasContainer,
   createCanvasContainer,
@@ -17,11 +23,13 @@ import {
   setRootNode,
   setStrictMode,
 } from "./runtime";
-import type { CanvasContext } from "./types";
+import type { CanvasContext, TextFont, TextMetrics, TextRect } from "./types";
 import { createWasmRenderer, type WasmRenderer } from "./wasmBridge";
 
 // biome-ignore lint/performance/noBarrelFile: public entrypoint exports
 export * from "./components";
+export { encodeTextMeasurement } from "./encoder";
+export { PathBuilder, PathVerb } from "./pathData";
 export * from "./types";
 
 const supportsWebGPU = typeof navigator !== "undefined" && "gpu" in navigator;
@@ -34,6 +42,14 @@ interface WasmDriverOptions {
 class WasmDriver {
   private renderer: WasmRenderer | null = null;
   private pending: Uint8Array | null = null;
+  private pendingImages: ReadonlyMap<number, ImageSource> | null = null;
+  private lastFrame: Uint8Array | null = null;
+  private lastImages: ReadonlyMap<number, ImageSource> = new Map();
+  // Ids registered with the renderer, and ids whose source is still loading.
+  private readonly registeredImages = new Set<number>();
+  private readonly loadingImages = new Set<number>();
+  // Font changes made before the renderer is ready, replayed once it is.
+  private readonly setup: ((renderer: WasmRenderer) => void)[] = [];
   private readonly onReady?: () => void;
   private readonly onError?: (error: unknown) => void;
 
@@ -48,11 +64,14 @@ class WasmDriver {
           return;
         }
         this.renderer = renderer;
+        for (const change of this.setup.splice(0)) {
+          this.applySetup(renderer, change);
+        }
         this.onReady?.();
         if (this.pending) {
-          this.renderer.apply(this.pending);
-          this.renderer.render();
+          this.enqueue(this.pending, this.pendingImages ?? new Map());
           this.pending = null;
+          this.pendingImages = null;
         }
       })
       .catch((error) => {
@@ -64,12 +83,55 @@ class WasmDriver {
   private handleFailure(error: unknown) {
     this.renderer = null;
     this.pending = null;
+    this.pendingImages = null;
     this.onError?.(error);
   }
 
-  enqueue(ops: Uint8Array) {
+  // Changes are applied now or once the renderer is ready, and report
+  // failures the same way either way.
+  configure(change: (renderer: WasmRenderer) => void) {
+    if (this.renderer) {
+      this.applySetup(this.renderer, change);
+    } else {
+      this.setup.push(change);
+    }
+  }
+
+  private applySetup(
+    renderer: WasmRenderer,
+    change: (renderer: WasmRenderer) => void
+  ) {
+    try {
+      change(renderer);
+    } catch (error) {
+      console.warn("[rvello] failed to configure renderer", error);
+    }
+  }
+
+  query<T>(read: (renderer: WasmRenderer) => T): T {
+    if (!this.renderer) {
+      throw new Error(
+        "[rvello] Text queries are available once onReady fires."
+      );
+    }
+    return read(this.renderer);
+  }
+
+  enqueue(ops: Uint8Array, images: ReadonlyMap<number, ImageSource>) {
     if (!this.renderer) {
       this.pending = ops;
+      this.pendingImages = images;
+      return;
+    }
+
+    this.lastFrame = ops;
+    this.lastImages = images;
+    this.syncImages(images);
+    this.draw(ops);
+  }
+
+  private draw(ops: Uint8Array) {
+    if (!this.renderer) {
       return;
     }
 
@@ -81,6 +143,72 @@ class WasmDriver {
       this.handleFailure(error);
     }
   }
+
+  // Sources that are already decoded are registered before the frame that
+  // draws them; the rest load in the background and redraw the latest frame
+  // once registered, which skips their images until then. Images that no
+  // node draws any more are released, and failed loads retry next frame.
+  private syncImages(images: ReadonlyMap<number, ImageSource>) {
+    for (const id of this.registeredImages) {
+      if (!images.has(id)) {
+        this.registeredImages.delete(id);
+        this.renderer?.unregisterImage(id);
+      }
+    }
+
+    for (const [id, source] of images) {
+      if (this.registeredImages.has(id) || this.loadingImages.has(id)) {
+        continue;
+      }
+
+      try {
+        const upload = readImageSource(source);
+        if (upload) {
+          this.registerImage(id, upload);
+          continue;
+        }
+      } catch (error) {
+        console.warn("[rvello] failed to read image", source, error);
+        continue;
+      }
+
+      this.loadingImages.add(id);
+      loadImageSource(source)
+        .then((upload) => {
+          // The image may have been removed while it loaded.
+          if (!(this.renderer && this.lastImages.has(id))) {
+            return;
+          }
+          this.registerImage(id, upload);
+          if (this.lastFrame) {
+            this.draw(this.lastFrame);
+          }
+        })
+        .catch((error) => {
+          console.warn("[rvello] failed to load image", source, error);
+        })
+        .finally(() => {
+          this.loadingImages.delete(id);
+        });
+    }
+  }
+
+  private registerImage(id: number, upload: ImageUpload) {
+    if (!this.renderer) {
+      return;
+    }
+    if (upload.kind === "encoded") {
+      this.renderer.registerEncodedImage(id, upload.bytes);
+    } else {
+      this.renderer.registerImage(
+        id,
+        upload.width,
+        upload.height,
+        upload.pixels
+      );
+    }
+    this.registeredImages.add(id);
+  }
 }
 
 type Instance = SceneNode;
@@ -453,10 +581,34 @@ export interface RendererOptions {
   onError?: (error: unknown) => void;
 }
 
+export interface FontOptions {
+  weight?: number;
+  style?: TextFont["style"];
+  /** Face to use within a TrueType/OpenType collection. */
+  index?: number;
+}
+
 export interface VelloRoot {
   render(children: ReactNode): void;
   unmount(): void;
   getContext(): CanvasContext;
+  /**
+   * Registers a font for `Text` nodes that ask for `family`. Font changes made
+   * before `onReady` apply once the renderer is ready; failures, such as font
+   * data that does not parse, are logged rather than thrown.
+   */
+  registerFont(bytes: Uint8Array, family: string, options?: FontOptions): void;
+  unregisterFont(family: string): void;
+  /** Families tried, in order, for characters missing from a node's fonts. */
+  setFontFallbacks(families: string[]): void;
+  /**
+   * Text queries take an op from `encodeTextMeasurement` and lay it out as the
+   * renderer draws it. They throw until `onReady` has fired.
+   */
+  measureText(op: Uint8Array): TextMetrics;
+  hitTestText(op: Uint8Array, x: number, y: number): number;
+  textCaret(op: Uint8Array, index: number): TextRect;
+  textSelection(op: Uint8Array, start: number, end: number): TextRect[];
 }
 
 export function createVelloRoot(
@@ -469,9 +621,9 @@ export function createVelloRoot(
 
   let wasmDriver: WasmDriver | null = null;
   const container = createCanvasContainer(canvas, {
-    onFrame(ops) {
+    onFrame(ops, images) {
       options.onFrame?.(ops);
-      wasmDriver?.enqueue(ops);
+      wasmDriver?.enqueue(ops, images);
     },
     softwareRenderer: false,
   });
@@ -511,6 +663,18 @@ export function createVelloRoot(
     backend: "webgpu",
   };
 
+  // Font changes redraw the scene, whose text is laid out with them.
+  const configure = (change: (renderer: WasmRenderer) => void) => {
+    wasmDriver?.configure(change);
+    scheduleRender(container);
+  };
+  const query = <T>(read: (renderer: WasmRenderer) => T): T => {
+    if (!wasmDriver) {
+      throw new Error("[rvello] WebGPU renderer unavailable.");
+    }
+    return wasmDriver.query(read);
+  };
+
   wasmDriver = new WasmDriver(canvas, {
     onReady: () => {
       options.onReady?.(context);
@@ -531,6 +695,35 @@ export function createVelloRoot(
     getContext() {
       return context;
     },
+    registerFont(bytes, family, fontOptions = {}) {
+      configure((renderer) =>
+        renderer.registerFont(
+          bytes,
+          family,
+          fontOptions.weight ?? 400,
+          fontOptions.style ?? "normal",
+          fontOptions.index
+        )
+      );
+    },
+    unregisterFont(family) {
+      configure((renderer) => renderer.unregisterFont(family));
+    },
+    setFontFallbacks(families) {
+      configure((renderer) => renderer.setFontFallbacks(families));
+    },
+    measureText(op) {
+      return query((renderer) => renderer.measureText(op));
+    },
+    hitTestText(op, x, y) {
+      return query((renderer) => renderer.hitTestText(op, x, y));
+    },
+    textCaret(op, index) {
+      return query((renderer) => renderer.textCaret(op, index));
+    },
+    textSelection(op, start, end) {
+      return query((renderer) => renderer.textSelection(op, start, end));
+    },
   };
 }
 
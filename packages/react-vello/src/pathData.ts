@@ -0,0 +1,100 @@
+import type { PathData } from "./types";
+
+export const PathVerb = {
+  MoveTo: 0,
+  LineTo: 1,
+  QuadTo: 2,
+  CubicTo: 3,
+  Close: 4,
+} as const;
+
+const POINTS_PER_VERB = [1, 1, 2, 3, 0];
+
+/** Builds `PathData` for `<Path d={...} />` without going through SVG strings. */
+export class PathBuilder {
+  private verbs: number[] = [];
+  private points: number[] = [];
+
+  moveTo(x: number, y: number): this {
+    this.verbs.push(PathVerb.MoveTo);
+    this.points.push(x, y);
+    return this;
+  }
+
+  lineTo(x: number, y: number): this {
+    this.verbs.push(PathVerb.LineTo);
+    this.points.push(x, y);
+    return this;
+  }
+
+  quadTo(x1: number, y1: number, x: number, y: number): this {
+    this.verbs.push(PathVerb.QuadTo);
+    this.points.push(x1, y1, x, y);
+    return this;
+  }
+
+  cubicTo(
+    x1: number,
+    y1: number,
+    x2: number,
+    y2: number,
+    x: number,
+    y: number
+  ): this {
+    this.verbs.push(PathVerb.CubicTo);
+    this.points.push(x1, y1, x2, y2, x, y);
+    return this;
+  }
+
+  close(): this {
+    this.verbs.push(PathVerb.Close);
+    return this;
+  }
+
+  build(): PathData {
+    return {
+      verbs: Uint8Array.from(this.verbs),
+      points: Float32Array.from(this.points),
+    };
+  }
+}
+
+export function pathDataToPath2D(data: PathData): Path2D {
+  const path = new Path2D();
+  const { verbs, points } = data;
+  let index = 0;
+  for (const verb of verbs) {
+    const count = POINTS_PER_VERB[verb];
+    if (count === undefined || index + count * 2 > points.length) {
+      throw new Error(`Invalid path verb ${verb} at point ${index / 2}`);
+    }
+    const p = points;
+    const i = index;
+    switch (verb) {
+      case PathVerb.MoveTo:
+        path.moveTo(p[i], p[i + 1]);
+        break;
+      case PathVerb.LineTo:
+        path.lineTo(p[i], p[i + 1]);
+        break;
+      case PathVerb.QuadTo:
+        path.quadraticCurveTo(p[i], p[i + 1], p[i + 2], p[i + 3]);
+        break;
+      case PathVerb.CubicTo:
+        path.bezierCurveTo(
+          p[i],
+          p[i + 1],
+          p[i + 2],
+          p[i + 3],
+          p[i + 4],
+          p[i + 5]
+        );
+        break;
+      default:
+        path.closePath();
+        break;
+    }
+    index += count * 2;
+  }
+  return path;
+}
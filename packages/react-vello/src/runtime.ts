@@ -1,12 +1,19 @@
 import { colorToCss, paintToRgba, rgbaToCss } from "./color";
 import { encodeFrame } from "./encoder";
-import { resolveCornerRadius } from "./geometry";
+import {
+  type CornerRadii,
+  hasCornerRadii,
+  normalizeCornerRadii,
+  resolveCornerRadii,
+} from "./geometry";
+import type { ImageSource } from "./imageSources";
 import {
   IDENTITY_MATRIX,
   invertTransform,
   multiplyTransforms,
   transformPoint,
 } from "./mat3";
+import { pathDataToPath2D } from "./pathData";
 import {
   resolveHitSlop,
   resolveImageOrigin,
@@ -33,6 +40,7 @@ import type {
   RadialGradientProps,
   RectProps,
   SceneNodeHandle,
+  SweepGradientProps,
   TextProps,
   Vec2,
 } from "./types";
@@ -55,6 +63,7 @@ export type HostType =
   | "Image"
   | "LinearGradient"
   | "RadialGradient"
+  | "SweepGradient"
   | "Mask"
   | "ClipPath";
 
@@ -67,6 +76,7 @@ export interface HostPropsMap {
   Image: ImageProps;
   LinearGradient: LinearGradientProps;
   RadialGradient: RadialGradientProps;
+  SweepGradient: SweepGradientProps;
   Mask: MaskProps;
   ClipPath: ClipPathProps;
 }
@@ -114,7 +124,7 @@ interface RectHitRegion {
   transform: Mat3;
   origin: Vec2;
   size: Vec2;
-  radius: number;
+  radii: CornerRadii;
 }
 
 type HitRegion = RectHitRegion;
@@ -138,7 +148,10 @@ export interface CanvasContainer {
   frameHandle: number | null;
   presentationSize: [number, number];
   dpr: number;
-  onFrame?: (ops: Uint8Array) => void;
+  onFrame?: (
+    ops: Uint8Array,
+    images: ReadonlyMap<number, ImageSource>
+  ) => void;
   softwareRendererActive: boolean;
   enableSoftwareRenderer(): void;
   hitRegions: HitRegion[];
@@ -173,7 +186,10 @@ const pointerHandlerProps: readonly (keyof NodeProps)[] = [
 const WHEEL_POINTER_ID = -1;
 
 interface ContainerOptions {
-  onFrame?: (ops: Uint8Array) => void;
+  onFrame?: (
+    ops: Uint8Array,
+    images: ReadonlyMap<number, ImageSource>
+  ) => void;
   softwareRenderer?: boolean;
 }
 
@@ -619,7 +635,7 @@ function renderContainer(container: CanvasContainer): void {
   if (container.onFrame) {
     const encoded = encodeFrame(container);
     if (encoded) {
-      container.onFrame(encoded);
+      container.onFrame(encoded.ops, encoded.images);
     }
   }
 }
@@ -686,8 +702,8 @@ function collectHitRegionsForNode(
       const [slopX, slopY] = resolveHitSlop(rectNode.props.hitSlop);
       const paddedOrigin: Vec2 = [origin[0] - slopX, origin[1] - slopY];
       const paddedSize: Vec2 = [size[0] + slopX * 2, size[1] + slopY * 2];
-      const radius = resolveCornerRadius(
-        rectNode.props.radius,
+      const radii = normalizeCornerRadii(
+        resolveCornerRadii(rectNode.props.radius),
         paddedSize[0],
         paddedSize[1]
       );
@@ -697,7 +713,7 @@ function collectHitRegionsForNode(
         transform: worldTransform,
         origin: paddedOrigin,
         size: paddedSize,
-        radius,
+        radii,
       };
       regions.push(region);
       regionMap.set(rectNode, region);
@@ -1551,68 +1567,60 @@ function pointInRectRegion(point: Vec2, region: RectHitRegion): boolean {
   if (px < ox || py < oy || px > ox + width || py > oy + height) {
     return false;
   }
-  const radius = region.radius;
-  if (radius <= 0) {
-    return true;
-  }
-
-  const clampedRadius = Math.min(
-    radius,
-    Math.min(Math.abs(width), Math.abs(height)) / 2
-  );
+  const [topLeft, topRight, bottomRight, bottomLeft] = region.radii;
   const right = ox + width;
   const bottom = oy + height;
 
-  if (px < ox + clampedRadius && py < oy + clampedRadius) {
-    return pointInsideCircle(
+  if (px < ox + topLeft[0] && py < oy + topLeft[1]) {
+    return pointInsideEllipse(
       px,
       py,
-      ox + clampedRadius,
-      oy + clampedRadius,
-      clampedRadius
+      ox + topLeft[0],
+      oy + topLeft[1],
+      topLeft
     );
   }
-  if (px > right - clampedRadius && py < oy + clampedRadius) {
-    return pointInsideCircle(
+  if (px > right - topRight[0] && py < oy + topRight[1]) {
+    return pointInsideEllipse(
       px,
       py,
-      right - clampedRadius,
-      oy + clampedRadius,
-      clampedRadius
+      right - topRight[0],
+      oy + topRight[1],
+      topRight
     );
   }
-  if (px < ox + clampedRadius && py > bottom - clampedRadius) {
-    return pointInsideCircle(
+  if (px > right - bottomRight[0] && py > bottom - bottomRight[1]) {
+    return pointInsideEllipse(
       px,
       py,
-      ox + clampedRadius,
-      bottom - clampedRadius,
-      clampedRadius
+      right - bottomRight[0],
+      bottom - bottomRight[1],
+      bottomRight
     );
   }
-  if (px > right - clampedRadius && py > bottom - clampedRadius) {
-    return pointInsideCircle(
+  if (px < ox + bottomLeft[0] && py > bottom - bottomLeft[1]) {
+    return pointInsideEllipse(
       px,
       py,
-      right - clampedRadius,
-      bottom - clampedRadius,
-      clampedRadius
+      ox + bottomLeft[0],
+      bottom - bottomLeft[1],
+      bottomLeft
     );
   }
 
   return true;
 }
 
-function pointInsideCircle(
+function pointInsideEllipse(
   px: number,
   py: number,
   cx: number,
   cy: number,
-  radius: number
+  radii: Vec2
 ): boolean {
-  const dx = px - cx;
-  const dy = py - cy;
-  return dx * dx + dy * dy <= radius * radius;
+  const dx = (px - cx) / radii[0];
+  const dy = (py - cy) / radii[1];
+  return dx * dx + dy * dy <= 1;
 }
 
 function getPointerPosition(
@@ -1688,10 +1696,14 @@ function renderRect(
   const opacity = parentState.opacity * (node.props.opacity ?? 1);
   const [x, y] = resolveRectOrigin(node.props);
   const [width, height] = resolveRectSize(node.props);
-  const radius = resolveCornerRadius(node.props.radius, width, height);
+  const radii = normalizeCornerRadii(
+    resolveCornerRadii(node.props.radius),
+    width,
+    height
+  );
 
   ctx.beginPath();
-  drawRoundedRectPath(ctx, x, y, width, height, radius);
+  drawRoundedRectPath(ctx, x, y, width, height, radii);
 
   const fill = resolvePaint(node.props.fill);
   if (fill) {
@@ -1730,7 +1742,10 @@ function renderPath(
   }
 
   try {
-    const path = new Path2D(pathData);
+    const path =
+      typeof pathData === "string"
+        ? new Path2D(pathData)
+        : pathDataToPath2D(pathData);
     const fillRule = node.props.fillRule === "evenodd" ? "evenodd" : "nonzero";
 
     const fill = resolvePaint(node.props.fill);
@@ -1746,7 +1761,7 @@ function renderPath(
       ctx.stroke(path);
     }
   } catch (error) {
-    console.warn("[rvello] Invalid SVG path data:", pathData, error);
+    console.warn("[rvello] Invalid path data:", pathData, error);
   }
 
   ctx.restore();
@@ -1761,7 +1776,12 @@ function renderText(
     return;
   }
 
-  const { text, font, align, maxWidth } = node.props;
+  const { spans, font, align, direction, maxWidth, fill, stroke } = node.props;
+  // The preview renderer draws spans with the element's own style, and text
+  // set along a path as straight lines.
+  const text = spans
+    ? spans.map((span) => span.text).join("")
+    : node.props.text;
   const origin = resolveTextOrigin(node.props);
   if (!text) {
     return;
@@ -1772,9 +1792,18 @@ function renderText(
 
   const opacity = parentState.opacity * (node.props.opacity ?? 1);
   const fillStyle =
-    resolvePaint(node.props.fill) ?? rgbaToCss({ r: 0, g: 0, b: 0, a: 1 });
+    fill === null
+      ? null
+      : (resolvePaint(fill) ?? rgbaToCss({ r: 0, g: 0, b: 0, a: 1 }));
   ctx.globalAlpha = opacity;
-  ctx.fillStyle = fillStyle;
+  // Decorations of stroke-only text take the stroke paint.
+  const decorationStyle =
+    fillStyle ?? (stroke ? resolvePaint(stroke.paint) : null);
+  if (decorationStyle) {
+    ctx.fillStyle = decorationStyle;
+  }
+  applyStroke(ctx, stroke);
+  const strokeFirst = node.props.paintOrder === "stroke";
 
   const fontSize = font?.size ?? 16;
   const fontStyle = font?.style ?? "normal";
@@ -1783,23 +1812,82 @@ function renderText(
   ctx.font = `${fontStyle} ${fontWeight} ${fontSize}px ${fontFamily}`;
   ctx.textBaseline = "alphabetic";
   ctx.textAlign = "left";
+  ctx.direction =
+    direction === "ltr" || direction === "rtl" ? direction : "inherit";
+  ctx.letterSpacing = `${node.props.letterSpacing ?? 0}px`;
+  ctx.wordSpacing = `${node.props.wordSpacing ?? 0}px`;
 
   const metrics = ctx.measureText("Mg");
   const ascent = metrics.actualBoundingBoxAscent || fontSize * 0.8;
   const descent = metrics.actualBoundingBoxDescent || fontSize * 0.2;
   const lineHeight = font?.lineHeight ?? ascent + descent;
 
-  const lines = wrapTextLines(ctx, text, maxWidth);
+  const lines = truncateTextLines(
+    ctx,
+    wrapTextLines(ctx, text, maxWidth),
+    node.props
+  );
   let y = origin[1] + ascent;
   for (const line of lines) {
-    const offsetX = alignOffset(align, maxWidth ?? 0, line.width);
-    ctx.fillText(line.text, origin[0] + offsetX, y);
+    const offsetX = alignOffset(
+      resolveLogicalAlign(align, direction),
+      maxWidth ?? 0,
+      line.width
+    );
+    const x = origin[0] + offsetX;
+    if (stroke && strokeFirst) {
+      ctx.strokeText(line.text, x, y);
+    }
+    if (fillStyle) {
+      ctx.fillText(line.text, x, y);
+    }
+    if (stroke && !strokeFirst) {
+      ctx.strokeText(line.text, x, y);
+    }
+    drawTextDecoration(
+      ctx,
+      node.props.decoration,
+      x,
+      y,
+      line.width,
+      ascent,
+      fontSize
+    );
     y += lineHeight;
   }
 
   ctx.restore();
 }
 
+// The preview renderer draws every decoration solid at approximate positions.
+function drawTextDecoration(
+  ctx: CanvasRenderingContext2D,
+  decoration: TextProps["decoration"],
+  x: number,
+  baseline: number,
+  width: number,
+  ascent: number,
+  fontSize: number
+): void {
+  if (!decoration) {
+    return;
+  }
+  const thickness = decoration.thickness ?? fontSize / 14;
+  const offsets: number[] = [];
+  if (decoration.underline) {
+    offsets.push(fontSize * 0.1);
+  }
+  if (decoration.lineThrough) {
+    offsets.push(-fontSize * 0.3);
+  }
+  if (decoration.overline) {
+    offsets.push(-ascent);
+  }
+  for (const offset of offsets) {
+    ctx.fillRect(x, baseline + offset, width, thickness);
+  }
+}
+
 function applyStroke(
   ctx: CanvasRenderingContext2D,
   stroke: RectProps["stroke"]
@@ -1823,6 +1911,7 @@ function applyStroke(
   } else {
     ctx.setLineDash([]);
   }
+  ctx.lineDashOffset = stroke.dashOffset ?? 0;
 }
 
 function applyCanvasTransform(
@@ -1902,6 +1991,44 @@ function wrapTextLines(
   return lines;
 }
 
+function truncateTextLines(
+  ctx: CanvasRenderingContext2D,
+  lines: { text: string; width: number }[],
+  props: TextProps
+): { text: string; width: number }[] {
+  const maxLines = props.maxLines ?? 0;
+  if (maxLines <= 0 || lines.length <= maxLines) {
+    return lines;
+  }
+
+  const visible = lines.slice(0, maxLines);
+  if (props.textOverflow !== "ellipsis") {
+    return visible;
+  }
+
+  const ellipsis = props.ellipsis || "\u2026";
+  const maxWidth = props.maxWidth ?? 0;
+  const last = visible[visible.length - 1];
+  let content = last.text;
+  let width = ctx.measureText(content + ellipsis).width;
+  while (content && maxWidth > 0 && width > maxWidth) {
+    content = Array.from(content).slice(0, -1).join("").trimEnd();
+    width = ctx.measureText(content + ellipsis).width;
+  }
+  visible[visible.length - 1] = { text: content + ellipsis, width };
+  return visible;
+}
+
+function resolveLogicalAlign(
+  align: TextProps["align"],
+  direction: TextProps["direction"]
+): TextProps["align"] {
+  if (direction !== "rtl" || align === "center") {
+    return align;
+  }
+  return align === "end" ? "start" : "end";
+}
+
 function alignOffset(
   align: TextProps["align"],
   maxWidth: number,
@@ -1917,28 +2044,67 @@ function alignOffset(
   return 0;
 }
 
+// Traces a rect with elliptical corners, clockwise from the top-left, from
+// radii already scaled to fit by `normalizeCornerRadii`.
 function drawRoundedRectPath(
   ctx: CanvasRenderingContext2D,
   x: number,
   y: number,
   width: number,
   height: number,
-  radius: number
+  radii: CornerRadii
 ): void {
-  if (radius <= 0) {
+  if (!hasCornerRadii(radii)) {
     ctx.rect(x, y, width, height);
     return;
   }
 
-  const r = Math.min(radius, Math.min(Math.abs(width), Math.abs(height)) / 2);
-  ctx.moveTo(x + r, y);
-  ctx.lineTo(x + width - r, y);
-  ctx.quadraticCurveTo(x + width, y, x + width, y + r);
-  ctx.lineTo(x + width, y + height - r);
-  ctx.quadraticCurveTo(x + width, y + height, x + width - r, y + height);
-  ctx.lineTo(x + r, y + height);
-  ctx.quadraticCurveTo(x, y + height, x, y + height - r);
-  ctx.lineTo(x, y + r);
-  ctx.quadraticCurveTo(x, y, x + r, y);
+  const left = Math.min(x, x + width);
+  const top = Math.min(y, y + height);
+  const right = Math.max(x, x + width);
+  const bottom = Math.max(y, y + height);
+  const [topLeft, topRight, bottomRight, bottomLeft] = radii;
+  const quarter = Math.PI / 2;
+  ctx.moveTo(left + topLeft[0], top);
+  ctx.lineTo(right - topRight[0], top);
+  ctx.ellipse(
+    right - topRight[0],
+    top + topRight[1],
+    topRight[0],
+    topRight[1],
+    0,
+    -quarter,
+    0
+  );
+  ctx.lineTo(right, bottom - bottomRight[1]);
+  ctx.ellipse(
+    right - bottomRight[0],
+    bottom - bottomRight[1],
+    bottomRight[0],
+    bottomRight[1],
+    0,
+    0,
+    quarter
+  );
+  ctx.lineTo(left + bottomLeft[0], bottom);
+  ctx.ellipse(
+    left + bottomLeft[0],
+    bottom - bottomLeft[1],
+    bottomLeft[0],
+    bottomLeft[1],
+    0,
+    quarter,
+    Math.PI
+  );
+  ctx.lineTo(left, top + topLeft[1]);
+  ctx.ellipse(
+    left + topLeft[0],
+    top + topLeft[1],
+    topLeft[0],
+    topLeft[1],
+    0,
+    Math.PI,
+    Math.PI + quarter
+  );
   ctx.closePath();
 }
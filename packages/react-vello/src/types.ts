@@ -17,6 +17,7 @@ export interface Stroke {
   cap?: "butt" | "square" | "round";
   miterLimit?: number;
   dash?: readonly number[];
+  dashOffset?: number;
 }
 
 export interface SolidPaint {
@@ -161,8 +162,28 @@ export interface GroupProps extends NodeProps {
     | "hard-light"
     | "soft-light"
     | "difference"
-    | "exclusion";
+    | "exclusion"
+    | "hue"
+    | "saturation"
+    | "color"
+    | "luminosity";
+  composite?:
+    | "source-over"
+    | "source-in"
+    | "source-out"
+    | "source-atop"
+    | "destination-over"
+    | "destination-in"
+    | "destination-out"
+    | "destination-atop"
+    | "destination"
+    | "copy"
+    | "xor"
+    | "lighter"
+    | "plus-lighter"
+    | "clear";
   clipPath?: string;
+  mask?: string;
 }
 
 export interface RectProps extends NodeProps {
@@ -175,8 +196,17 @@ export interface RectProps extends NodeProps {
   stroke?: Stroke;
 }
 
+/**
+ * Compact path representation that skips SVG parsing. `verbs` holds one
+ * `PathVerb` per segment and `points` holds the x/y pairs they consume.
+ */
+export interface PathData {
+  verbs: Uint8Array;
+  points: Float32Array;
+}
+
 export interface PathProps extends NodeProps {
-  d: string;
+  d: string | PathData;
   fill?: Paint;
   stroke?: Stroke;
   fillRule?: "nonzero" | "evenodd";
@@ -185,19 +215,130 @@ export interface PathProps extends NodeProps {
 export interface TextFont {
   family: string;
   size: number;
+  /** Face weight or `wght` axis value; bold is synthesized when neither fits. */
   weight?: number | "normal" | "bold";
-  style?: "normal" | "italic";
+  style?: "normal" | "italic" | "oblique";
+  /** `font-stretch` percentage mapped to the `wdth` axis, defaulting to 100. */
+  width?: number;
+  /** Oblique angle in degrees, defaulting to 14; also used for synthetic italics. */
+  slant?: number;
+  /** Axis values by tag, such as `{ opsz: 32, GRAD: 50 }`, applied last. */
+  variations?: Readonly<Record<string, number>>;
   lineHeight?: number;
 }
 
+export interface TextDecoration {
+  underline?: boolean;
+  lineThrough?: boolean;
+  overline?: boolean;
+  style?: "solid" | "dashed" | "wavy";
+  /** Paint of the lines, defaulting to the text fill. */
+  color?: Paint;
+  /** Line thickness in pixels, defaulting to the font's underline and strikeout metrics. */
+  thickness?: number;
+}
+
+/** A styled run of a `Text` element; unset fields inherit from the element. */
+export interface TextSpan {
+  text: string;
+  font?: Partial<TextFont>;
+  fill?: Paint | null;
+  stroke?: Stroke | null;
+  paintOrder?: "normal" | "stroke";
+  letterSpacing?: number;
+  decoration?: TextDecoration;
+}
+
+/** A path a `Text` element is set along, mirroring SVG `textPath`. */
+export interface TextPath {
+  d: string | PathData;
+  /** Distance along the path at which `align` anchors each line. */
+  startOffset?: number;
+  /** `right` runs the path backwards, setting the text on its other side. */
+  side?: "left" | "right";
+}
+
 export interface TextProps extends NodeProps {
   text?: string;
+  /** Styled runs laid out as one paragraph in place of `text`. */
+  spans?: TextSpan[];
   children?: ReactNode;
   origin?: Vec2;
   maxWidth?: number;
-  align?: "start" | "center" | "end";
+  /** `justify` stretches word gaps on every wrapped line except a paragraph's last. */
+  align?: "start" | "center" | "end" | "justify";
+  /** Extra space in pixels after every character. */
+  letterSpacing?: number;
+  /** Extra space in pixels after every word separator. */
+  wordSpacing?: number;
+  /** Paragraph direction; `auto` uses the first strong character. */
+  direction?: "auto" | "ltr" | "rtl";
+  /**
+   * `normal` collapses runs of whitespace; `pre-wrap` preserves them and `pre`
+   * also keeps each line from wrapping at `maxWidth`.
+   */
+  whiteSpace?: "normal" | "pre-wrap" | "pre";
+  /** `break-word` breaks words wider than `maxWidth` between graphemes. */
+  overflowWrap?: "normal" | "break-word";
+  /** Maximum number of lines to draw; later lines are dropped. */
+  maxLines?: number;
+  /** `clip` clips lines to `maxWidth`; `ellipsis` ends cut-off text with `ellipsis`. */
+  textOverflow?: "visible" | "clip" | "ellipsis";
+  /** String that marks cut-off text, defaulting to "…". */
+  ellipsis?: string;
+  /**
+   * `vertical-rl` sets lines as columns running down from the top of the text,
+   * stacked right to left; `maxWidth` then limits the column height.
+   */
+  writingMode?: "horizontal-tb" | "vertical-rl";
+  /** Glyph orientation in vertical text; `mixed` sets only CJK upright. */
+  textOrientation?: "mixed" | "upright" | "sideways";
+  /**
+   * Sets each glyph along a path, turned to its tangent. Lines are not wrapped
+   * and decorations are not drawn; glyphs past the ends of an open path are
+   * dropped, while closed paths wrap around.
+   */
+  path?: TextPath;
   font: TextFont;
-  fill?: Paint;
+  /** Glyph fill, black by default; `null` draws the stroke alone. */
+  fill?: Paint | null;
+  stroke?: Stroke;
+  /** `stroke` draws the stroke beneath the fill, for halos around map labels. */
+  paintOrder?: "normal" | "stroke";
+  decoration?: TextDecoration;
+}
+
+/**
+ * A laid out line, positioned relative to the text origin. Vertical metrics come from the
+ * tallest span on the line.
+ */
+export interface TextLineMetrics {
+  text: string;
+  width: number;
+  x: number;
+  baseline: number;
+  ascent: number;
+  descent: number;
+  height: number;
+}
+
+/** Layout of a `Text` element as returned by the renderer's `measure_text`. */
+export interface TextMetrics {
+  width: number;
+  height: number;
+  lines: TextLineMetrics[];
+}
+
+/**
+ * A caret or selection rectangle from the renderer's `text_caret` and `text_selection`,
+ * relative to the text origin. Text offsets passed to and returned by the text queries
+ * are UTF-16 indices into the element's text, or its spans' text joined.
+ */
+export interface TextRect {
+  x: number;
+  y: number;
+  width: number;
+  height: number;
 }
 
 export interface ImageProps extends NodeProps {
@@ -208,7 +349,7 @@ export interface ImageProps extends NodeProps {
   height?: number;
   colorSpace?: "srgb" | "display-p3";
   opacityMap?: string;
-  fit?: "cover" | "contain" | "stretch";
+  fit?: "cover" | "contain" | "stretch" | "none";
 }
 
 export interface LinearGradientProps extends NodeProps {
@@ -216,17 +357,32 @@ export interface LinearGradientProps extends NodeProps {
   from: Vec2;
   to: Vec2;
   stops: readonly { offset: number; color: string | RgbaColor }[];
+  extend?: "pad" | "repeat" | "reflect";
 }
 
 export interface RadialGradientProps extends NodeProps {
   id: string;
   center: Vec2;
   radius: number;
+  focus?: Vec2;
+  focusRadius?: number;
+  stops: readonly { offset: number; color: string | RgbaColor }[];
+  extend?: "pad" | "repeat" | "reflect";
+}
+
+export interface SweepGradientProps extends NodeProps {
+  id: string;
+  center: Vec2;
+  /** Angles in degrees, clockwise from the positive x axis; default 0 and 360. */
+  startAngle?: number;
+  endAngle?: number;
   stops: readonly { offset: number; color: string | RgbaColor }[];
+  extend?: "pad" | "repeat" | "reflect";
 }
 
 export interface MaskProps extends NodeProps {
   id: string;
+  mode?: "alpha" | "luminance";
   children?: ReactNode;
 }
 
@@ -244,6 +400,7 @@ export interface CanvasIntrinsicElements {
   Image: ImageProps;
   LinearGradient: LinearGradientProps;
   RadialGradient: RadialGradientProps;
+  SweepGradient: SweepGradientProps;
   Mask: MaskProps;
   ClipPath: ClipPathProps;
 }
@@ -233,6 +233,21 @@ function takeFromExternrefTable0(idx) {
     wasm.__externref_table_dealloc(idx);
     return value;
 }
+
+function passArrayJsValueToWasm0(array, malloc) {
+    const ptr = malloc(array.length * 4, 4) >>> 0;
+    for (let i = 0; i < array.length; i++) {
+        const add = addToExternrefTable0(array[i]);
+        getDataViewMemory0().setUint32(ptr + 4 * i, add, true);
+    }
+    WASM_VECTOR_LEN = array.length;
+    return ptr;
+}
+
+export function wasm_start() {
+    wasm.wasm_start();
+}
+
 /**
  * @param {HTMLCanvasElement} canvas
  * @returns {Promise<RendererHandle>}
@@ -242,16 +257,12 @@ export function create_renderer(canvas) {
     return ret;
 }
 
-export function wasm_start() {
-    wasm.wasm_start();
+function wasm_bindgen__convert__closures_____invoke__h614e34147b4393f3(arg0, arg1, arg2) {
+    wasm.wasm_bindgen__convert__closures_____invoke__h614e34147b4393f3(arg0, arg1, arg2);
 }
 
-function wasm_bindgen__convert__closures_____invoke__h1e3f4f5e5b6e003f(arg0, arg1, arg2) {
-    wasm.wasm_bindgen__convert__closures_____invoke__h1e3f4f5e5b6e003f(arg0, arg1, arg2);
-}
-
-function wasm_bindgen__convert__closures_____invoke__h2a0f84921f614cc0(arg0, arg1, arg2, arg3) {
-    wasm.wasm_bindgen__convert__closures_____invoke__h2a0f84921f614cc0(arg0, arg1, arg2, arg3);
+function wasm_bindgen__convert__closures_____invoke__hb33e66535c9be72b(arg0, arg1, arg2, arg3) {
+    wasm.wasm_bindgen__convert__closures_____invoke__hb33e66535c9be72b(arg0, arg1, arg2, arg3);
 }
 
 const __wbindgen_enum_GpuAddressMode = ["clamp-to-edge", "repeat", "mirror-repeat"];
@@ -330,6 +341,137 @@ export class RendererHandle {
         wasm.__wbg_rendererhandle_free(ptr, 0);
     }
     /**
+     * Returns the caret before UTF-16 offset `index` into the text of a `Text` or `RichText`
+     * opcode as a zero-width `{ x, y, width, height }` spanning its line, relative to the
+     * text origin. Carets of vertical text are zero-height and span their column.
+     * @param {Uint8Array} op
+     * @param {number} index
+     * @returns {any}
+     */
+    text_caret(op, index) {
+        const ret = wasm.rendererhandle_text_caret(this.__wbg_ptr, op, index);
+        if (ret[2]) {
+            throw takeFromExternrefTable0(ret[1]);
+        }
+        return takeFromExternrefTable0(ret[0]);
+    }
+    /**
+     * Lays out a single encoded `Text` or `RichText` opcode exactly as `apply` would draw it
+     * and returns `{ width, height, lines: [{ text, width, x, baseline, ascent, descent,
+     * height }] }`, with positions relative to the text origin. Lines of vertical text are
+     * measured along their columns, with the baseline at the column's centre line.
+     * @param {Uint8Array} op
+     * @returns {any}
+     */
+    measure_text(op) {
+        const ret = wasm.rendererhandle_measure_text(this.__wbg_ptr, op);
+        if (ret[2]) {
+            throw takeFromExternrefTable0(ret[1]);
+        }
+        return takeFromExternrefTable0(ret[0]);
+    }
+    /**
+     * Returns the UTF-16 offset into the text of a `Text` or `RichText` opcode of the
+     * grapheme boundary nearest to `(x, y)`, relative to the text origin.
+     * @param {Uint8Array} op
+     * @param {number} x
+     * @param {number} y
+     * @returns {number}
+     */
+    hit_test_text(op, x, y) {
+        const ret = wasm.rendererhandle_hit_test_text(this.__wbg_ptr, op, x, y);
+        if (ret[2]) {
+            throw takeFromExternrefTable0(ret[1]);
+        }
+        return ret[0] >>> 0;
+    }
+    /**
+     * Registers a TrueType/OpenType face for `Text` runs that ask for `family`.
+     * `style` is a CSS font style and `index` selects a face within a TTC collection.
+     * @param {Uint8Array} bytes
+     * @param {string} family
+     * @param {number} weight
+     * @param {string} style
+     * @param {number | null} [index]
+     */
+    register_font(bytes, family, weight, style, index) {
+        const ptr0 = passStringToWasm0(family, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+        const len0 = WASM_VECTOR_LEN;
+        const ptr1 = passStringToWasm0(style, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+        const len1 = WASM_VECTOR_LEN;
+        const ret = wasm.rendererhandle_register_font(this.__wbg_ptr, bytes, ptr0, len0, weight, ptr1, len1, isLikeNone(index) ? 0x100000001 : (index) >>> 0);
+        if (ret[1]) {
+            throw takeFromExternrefTable0(ret[0]);
+        }
+    }
+    /**
+     * Registers straight-alpha RGBA8 pixels under `id` for use by the `Image` opcode,
+     * replacing any image previously registered with that id.
+     * @param {number} id
+     * @param {number} width
+     * @param {number} height
+     * @param {Uint8Array} pixels
+     */
+    register_image(id, width, height, pixels) {
+        const ret = wasm.rendererhandle_register_image(this.__wbg_ptr, id, width, height, pixels);
+        if (ret[1]) {
+            throw takeFromExternrefTable0(ret[0]);
+        }
+    }
+    /**
+     * Returns the `{ x, y, width, height }` rectangles covering the UTF-16 range
+     * `start..end` of the text of a `Text` or `RichText` opcode, relative to the text
+     * origin.
+     * @param {Uint8Array} op
+     * @param {number} start
+     * @param {number} end
+     * @returns {any}
+     */
+    text_selection(op, start, end) {
+        const ret = wasm.rendererhandle_text_selection(this.__wbg_ptr, op, start, end);
+        if (ret[2]) {
+            throw takeFromExternrefTable0(ret[1]);
+        }
+        return takeFromExternrefTable0(ret[0]);
+    }
+    /**
+     * Removes every face registered for `family`.
+     * @param {string} family
+     */
+    unregister_font(family) {
+        const ptr0 = passStringToWasm0(family, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+        const len0 = WASM_VECTOR_LEN;
+        wasm.rendererhandle_unregister_font(this.__wbg_ptr, ptr0, len0);
+    }
+    /**
+     * @param {number} id
+     */
+    unregister_image(id) {
+        wasm.rendererhandle_unregister_image(this.__wbg_ptr, id);
+    }
+    /**
+     * Sets the families tried, in order, for characters missing from a run's own fonts.
+     * @param {string[]} families
+     */
+    set_font_fallbacks(families) {
+        const ptr0 = passArrayJsValueToWasm0(families, wasm.__wbindgen_malloc);
+        const len0 = WASM_VECTOR_LEN;
+        wasm.rendererhandle_set_font_fallbacks(this.__wbg_ptr, ptr0, len0);
+    }
+    /**
+     * Decodes PNG or JPEG bytes and registers the result under `id`.
+     * @param {number} id
+     * @param {Uint8Array} bytes
+     */
+    register_encoded_image(id, bytes) {
+        const ret = wasm.rendererhandle_register_encoded_image(this.__wbg_ptr, id, bytes);
+        if (ret[1]) {
+            throw takeFromExternrefTable0(ret[0]);
+        }
+    }
+    /**
+     * Encodes a frame of opcodes into the scene drawn by `render`. Nodes with invalid
+     * data are skipped with a console warning rather than failing the frame.
      * @param {Uint8Array} ops
      */
     apply(ops) {
@@ -769,7 +911,7 @@ function __wbg_get_imports() {
                 const a = state0.a;
                 state0.a = 0;
                 try {
-                    return wasm_bindgen__convert__closures_____invoke__h2a0f84921f614cc0(a, state0.b, arg0, arg1);
+                    return wasm_bindgen__convert__closures_____invoke__hb33e66535c9be72b(a, state0.b, arg0, arg1);
                 } finally {
                     state0.a = a;
                 }
@@ -1409,6 +1551,9 @@ function __wbg_get_imports() {
         const ret = arg0.then(arg1, arg2);
         return ret;
     };
+    imports.wbg.__wbg_warn_1d74dddbe2fd1dbb = function(arg0) {
+        console.warn(arg0);
+    };
     imports.wbg.__wbg_width_9ea2df52b5d2c909 = function(arg0) {
         const ret = arg0.width;
         return ret;
@@ -1424,9 +1569,9 @@ function __wbg_get_imports() {
         const ret = getStringFromWasm0(arg0, arg1);
         return ret;
     };
-    imports.wbg.__wbindgen_cast_443332637da6f2f5 = function(arg0, arg1) {
-        // Cast intrinsic for `Closure(Closure { dtor_idx: 160, function: Function { arguments: [Externref], shim_idx: 161, ret: Unit, inner_ret: Some(Unit) }, mutable: true }) -> Externref`.
-        const ret = makeMutClosure(arg0, arg1, wasm.wasm_bindgen__closure__destroy__hb8467f3e511a960c, wasm_bindgen__convert__closures_____invoke__h1e3f4f5e5b6e003f);
+    imports.wbg.__wbindgen_cast_b2e692da0ea8478a = function(arg0, arg1) {
+        // Cast intrinsic for `Closure(Closure { dtor_idx: 464, function: Function { arguments: [Externref], shim_idx: 465, ret: Unit, inner_ret: Some(Unit) }, mutable: true }) -> Externref`.
+        const ret = makeMutClosure(arg0, arg1, wasm.wasm_bindgen__closure__destroy__hab47f4e828978c73, wasm_bindgen__convert__closures_____invoke__h614e34147b4393f3);
         return ret;
     };
     imports.wbg.__wbindgen_cast_d6cd19b81560fd6e = function(arg0) {
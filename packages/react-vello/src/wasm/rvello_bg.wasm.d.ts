@@ -4,12 +4,22 @@ export const memory: WebAssembly.Memory;
 export const __wbg_rendererhandle_free: (a: number, b: number) => void;
 export const create_renderer: (a: any) => any;
 export const rendererhandle_apply: (a: number, b: any) => [number, number];
+export const rendererhandle_hit_test_text: (a: number, b: any, c: number, d: number) => [number, number, number];
+export const rendererhandle_measure_text: (a: number, b: any) => [number, number, number];
+export const rendererhandle_register_encoded_image: (a: number, b: number, c: any) => [number, number];
+export const rendererhandle_register_font: (a: number, b: any, c: number, d: number, e: number, f: number, g: number, h: number) => [number, number];
+export const rendererhandle_register_image: (a: number, b: number, c: number, d: number, e: any) => [number, number];
 export const rendererhandle_render: (a: number) => [number, number];
 export const rendererhandle_resize: (a: number, b: number, c: number) => void;
+export const rendererhandle_set_font_fallbacks: (a: number, b: number, c: number) => void;
+export const rendererhandle_text_caret: (a: number, b: any, c: number) => [number, number, number];
+export const rendererhandle_text_selection: (a: number, b: any, c: number, d: number) => [number, number, number];
+export const rendererhandle_unregister_font: (a: number, b: number, c: number) => void;
+export const rendererhandle_unregister_image: (a: number, b: number) => void;
 export const wasm_start: () => void;
-export const wasm_bindgen__convert__closures_____invoke__h1e3f4f5e5b6e003f: (a: number, b: number, c: any) => void;
-export const wasm_bindgen__closure__destroy__hb8467f3e511a960c: (a: number, b: number) => void;
-export const wasm_bindgen__convert__closures_____invoke__h2a0f84921f614cc0: (a: number, b: number, c: any, d: any) => void;
+export const wasm_bindgen__convert__closures_____invoke__h614e34147b4393f3: (a: number, b: number, c: any) => void;
+export const wasm_bindgen__closure__destroy__hab47f4e828978c73: (a: number, b: number) => void;
+export const wasm_bindgen__convert__closures_____invoke__hb33e66535c9be72b: (a: number, b: number, c: any, d: any) => void;
 export const __wbindgen_malloc: (a: number, b: number) => number;
 export const __wbindgen_realloc: (a: number, b: number, c: number, d: number) => number;
 export const __wbindgen_exn_store: (a: number) => void;
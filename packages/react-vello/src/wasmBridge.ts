@@ -1,3 +1,5 @@
+import type { TextFont, TextMetrics, TextRect } from "./types";
+
 let wasmModulePromise: Promise<WasmModule | null> | null = null;
 
 type WasmModule = typeof import("./wasm/rvello.js");
@@ -5,6 +7,30 @@ type WasmModule = typeof import("./wasm/rvello.js");
 export interface WasmRenderer {
   apply(data: Uint8Array): void;
   render(): void;
+  /** Registers straight-alpha RGBA8 pixels for Image opcodes using `id`. */
+  registerImage(
+    id: number,
+    width: number,
+    height: number,
+    pixels: Uint8Array
+  ): void;
+  /** Decodes PNG or JPEG bytes and registers them under `id`. */
+  registerEncodedImage(id: number, bytes: Uint8Array): void;
+  unregisterImage(id: number): void;
+  /** Registers a TrueType/OpenType face; `index` selects a face in a TTC. */
+  registerFont(
+    bytes: Uint8Array,
+    family: string,
+    weight: number,
+    style: NonNullable<TextFont["style"]>,
+    index?: number
+  ): void;
+  unregisterFont(family: string): void;
+  setFontFallbacks(families: string[]): void;
+  measureText(op: Uint8Array): TextMetrics;
+  hitTestText(op: Uint8Array, x: number, y: number): number;
+  textCaret(op: Uint8Array, index: number): TextRect;
+  textSelection(op: Uint8Array, start: number, end: number): TextRect[];
 }
 
 export async function createWasmRenderer(
@@ -28,6 +54,36 @@ export async function createWasmRenderer(
       render() {
         handle.render();
       },
+      registerImage(id, width, height, pixels) {
+        handle.register_image(id, width, height, pixels);
+      },
+      registerEncodedImage(id, bytes) {
+        handle.register_encoded_image(id, bytes);
+      },
+      unregisterImage(id) {
+        handle.unregister_image(id);
+      },
+      registerFont(bytes, family, weight, style, index) {
+        handle.register_font(bytes, family, weight, style, index);
+      },
+      unregisterFont(family) {
+        handle.unregister_font(family);
+      },
+      setFontFallbacks(families) {
+        handle.set_font_fallbacks(families);
+      },
+      measureText(op) {
+        return handle.measure_text(op) as TextMetrics;
+      },
+      hitTestText(op, x, y) {
+        return handle.hit_test_text(op, x, y);
+      },
+      textCaret(op, index) {
+        return handle.text_caret(op, index) as TextRect;
+      },
+      textSelection(op, start, end) {
+        return handle.text_selection(op, start, end) as TextRect[];
+      },
     };
   } catch (error) {
     console.warn("[rvello] wasm renderer initialization failed", error);